@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Glob;
+
+/// Whether a laptop lid is open or closed.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Lid {
+    Open,
+    Closed,
+}
+
+/// A USB device identified by its vendor and, optionally, product ID, as four hex digits each.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UsbDevice {
+    pub vendor: Glob,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<Glob>,
+}
+
+/// Machine state a profile requires before `reconcile` selects it. Every condition that is set
+/// must hold. The daemon only checks them when outputs change; opening the lid or plugging in
+/// power on its own doesn't wake it, so run `autorandr reconcile` from an acpid or udev rule to
+/// react to those.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Conditions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lid: Option<Lid>,
+    /// Whether the machine runs on mains power.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ac: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<Glob>,
+    /// USB devices that must all be present, eg those of a dock.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usb: Vec<UsbDevice>,
+}
+
+impl Conditions {
+    /// Whether every condition holds on `machine`. Conditions on state that can't be determined
+    /// don't hold.
+    pub fn hold(&self, machine: &Machine) -> bool {
+        let lid = self.lid.map(|lid| machine.lid() == Some(lid));
+        let ac = self.ac.map(|ac| machine.ac_online() == Some(ac));
+        let hostname = self
+            .hostname
+            .as_ref()
+            .map(|glob| machine.hostname().is_some_and(|h| glob.matches(&h)));
+        let usb = (!self.usb.is_empty()).then(|| {
+            let devices = machine.usb_devices();
+            self.usb.iter().all(|wanted| {
+                devices.iter().any(|(vendor, product)| {
+                    wanted.vendor.matches(vendor)
+                        && wanted.product.as_ref().is_none_or(|p| p.matches(product))
+                })
+            })
+        });
+
+        let checks = [("lid", lid), ("ac", ac), ("hostname", hostname), ("usb", usb)];
+        for (name, holds) in checks {
+            if holds == Some(false) {
+                log::debug!("condition '{}' doesn't hold", name);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Where machine state is read from: the procfs and sysfs mount points, which tests point at a
+/// fake tree.
+#[derive(Clone, Debug)]
+pub struct Machine {
+    proc: PathBuf,
+    sys: PathBuf,
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new("/proc", "/sys")
+    }
+}
+
+impl Machine {
+    pub fn new<P: Into<PathBuf>, S: Into<PathBuf>>(proc: P, sys: S) -> Machine {
+        Machine {
+            proc: proc.into(),
+            sys: sys.into(),
+        }
+    }
+
+    /// The lid state ACPI reports, as "state:      open" in `/proc/acpi/button/lid/*/state`;
+    /// sysfs has no standard attribute for it.
+    pub fn lid(&self) -> Option<Lid> {
+        entries(&self.proc.join("acpi/button/lid")).iter().find_map(|dir| {
+            match read(&dir.join("state"))?.split_whitespace().last()? {
+                "open" => Some(Lid::Open),
+                "closed" => Some(Lid::Closed),
+                _ => None,
+            }
+        })
+    }
+
+    /// Whether a mains or USB power supply, such as a USB-C charger, is online. Machines with
+    /// neither kind of supply nor a battery count as online.
+    pub fn ac_online(&self) -> Option<bool> {
+        let supplies = entries(&self.sys.join("class/power_supply"));
+        let of_type = |kinds: &[&str]| {
+            supplies
+                .iter()
+                .filter(|dir| read(&dir.join("type")).is_some_and(|t| kinds.contains(&t.as_str())))
+                .collect::<Vec<&PathBuf>>()
+        };
+        let chargers = of_type(&["Mains", "USB"]);
+        if chargers.is_empty() {
+            return of_type(&["Battery"]).is_empty().then_some(true);
+        }
+        Some(chargers.iter().any(|dir| read(&dir.join("online")).as_deref() == Some("1")))
+    }
+
+    pub fn hostname(&self) -> Option<String> {
+        read(&self.proc.join("sys/kernel/hostname"))
+    }
+
+    /// The vendor and product IDs of the connected USB devices.
+    pub fn usb_devices(&self) -> Vec<(String, String)> {
+        entries(&self.sys.join("bus/usb/devices"))
+            .iter()
+            .filter_map(|dir| Some((read(&dir.join("idVendor"))?, read(&dir.join("idProduct"))?)))
+            .collect()
+    }
+}
+
+/// The entries of `dir`, in name order; none if it can't be read.
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<PathBuf>>(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort();
+    entries
+}
+
+/// The trimmed contents of a procfs or sysfs file.
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TempDir;
+
+    #[test]
+    fn check_conditions_against_fake_tree() {
+        let root = TempDir::new("conditions");
+        let write = |path: &str, contents: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write("proc/acpi/button/lid/LID0/state", "state:      closed\n");
+        write("proc/sys/kernel/hostname", "work-laptop\n");
+        write("sys/class/power_supply/AC/type", "Mains\n");
+        write("sys/class/power_supply/AC/online", "1\n");
+        write("sys/class/power_supply/BAT0/type", "Battery\n");
+        write("sys/bus/usb/devices/3-1/idVendor", "17ef\n");
+        write("sys/bus/usb/devices/3-1/idProduct", "30a9\n");
+        write("sys/bus/usb/devices/usb3/idVendor", "1d6b\n");
+        write("sys/bus/usb/devices/usb3/idProduct", "0003\n");
+
+        let machine = Machine::new(root.join("proc"), root.join("sys"));
+        let holds = |yaml: &str| serde_yaml::from_str::<Conditions>(yaml).unwrap().hold(&machine);
+        let results = [
+            holds("{}"),
+            holds("lid: closed"),
+            holds("lid: open"),
+            holds("{ac: true, hostname: work-*}"),
+            holds("ac: false"),
+            holds("hostname: home-*"),
+            holds("usb: [{vendor: 17EF, product: 30a9}, {vendor: 1d6b}]"),
+            holds("usb: [{vendor: 17ef, product: \"0000\"}]"),
+        ];
+
+        write("sys/class/power_supply/AC/online", "0\n");
+        let unplugged = holds("ac: false");
+        write("sys/class/power_supply/ucsi-source-psy-USBC000:001/type", "USB\n");
+        write("sys/class/power_supply/ucsi-source-psy-USBC000:001/online", "1\n");
+        let usb_charging = holds("ac: true");
+        fs::remove_dir_all(root.join("proc/acpi")).unwrap();
+        let without_lid = holds("lid: open");
+
+        assert_eq!(results, [true, true, false, true, false, false, true, false]);
+        assert!(unplugged);
+        assert!(usb_charging);
+        assert!(!without_lid);
+    }
+}
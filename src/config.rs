@@ -1,47 +1,165 @@
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::fs;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
 
+use crate::conditions::{Conditions, Machine};
 use crate::errors::{Error, Result};
-use crate::output::Output;
+use crate::fallback::{FallbackPolicy, UnlistedOutputs};
+use crate::hooks::Hooks;
+use crate::matcher::{self, EdidMatch};
+use crate::output::{Edids, Output};
 
 /// Representation of a known collection of devices.
-#[derive(Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct Profile {
+    #[serde(default)]
     pub(crate) outputs: BTreeMap<String, Output>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) profile_name: Option<String>,
+    /// Name of a profile whose outputs, priority, and hooks this profile inherits; anything set
+    /// here overrides the inherited value, output by output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) extends: Option<String>,
+    /// Profiles with higher priority are preferred over those with lower priority, regardless of
+    /// how many outputs they cover. Defaults to 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub(crate) hooks: Hooks,
+    /// What mirror mode does when the mirrored outputs have no resolution in common. Only
+    /// meaningful for the "mirror" profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mirror_fallback: Option<MirrorFallback>,
+    /// What to do with connected outputs that the profile doesn't list. Defaults to turning them
+    /// off; list an output with `off: true` to turn it off whatever the policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) unlisted_outputs: Option<UnlistedOutputs>,
+    /// Machine state, such as the lid being closed, that `reconcile` requires before selecting
+    /// the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) conditions: Option<Conditions>,
 
     #[serde(skip)]
     name: String,
-    #[serde(skip)]
-    set: HashSet<String>,
+}
+
+/// How mirror mode handles outputs without a common resolution.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorFallback {
+    /// Refuse to mirror.
+    #[default]
+    Fail,
+    /// Run every other output at its preferred mode, scaled from the source output's resolution.
+    Scale,
 }
 
 // public methods
 impl Profile {
-    pub fn is_available(&self, available_edids: &HashSet<String>) -> bool {
-        log::debug!("{:?}", self.set);
-        self.set.is_subset(available_edids)
-    }
-
     pub fn name(&self) -> &str {
         self.name.as_str()
     }
+
+    pub fn priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
+    }
+
+    pub fn mirror_fallback(&self) -> MirrorFallback {
+        self.mirror_fallback.unwrap_or_default()
+    }
+
+    pub fn unlisted_outputs(&self) -> UnlistedOutputs {
+        self.unlisted_outputs.unwrap_or_default()
+    }
+
+    /// Whether the profile's conditions, if any, hold on `machine`.
+    pub fn conditions_hold(&self, machine: &Machine) -> bool {
+        self.conditions.as_ref().is_none_or(|c| c.hold(machine))
+    }
+
+    /// Resolve this profile's `extends` chain against `profiles`, returning the merged profile.
+    pub fn inherit(&self, profiles: &[Profile]) -> Result<Profile> {
+        let mut chain = vec![self];
+        while let Some(parent) = &chain[chain.len() - 1].extends {
+            let child = chain[chain.len() - 1];
+            if chain.iter().any(|p| p.name == *parent) {
+                let names = chain
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .chain([parent.as_str()])
+                    .collect::<Vec<&str>>();
+                return Err(Error::ProfileInheritanceCycle(names.join(" -> ")));
+            }
+            let parent = profiles
+                .iter()
+                .find(|p| p.name == *parent)
+                .ok_or_else(|| Error::MissingParentProfile(child.name.clone(), parent.clone()))?;
+            chain.push(parent);
+        }
+
+        let mut merged = self.clone();
+        merged.outputs = BTreeMap::new();
+        merged.priority = None;
+        merged.hooks = Hooks::default();
+        merged.mirror_fallback = None;
+        merged.unlisted_outputs = None;
+        merged.conditions = None;
+        for ancestor in chain.iter().rev() {
+            merged
+                .outputs
+                .extend(ancestor.outputs.iter().map(|(k, v)| (k.clone(), v.clone())));
+            merged.priority = ancestor.priority.or(merged.priority);
+            merged.hooks.preswitch = ancestor.hooks.preswitch.clone().or(merged.hooks.preswitch);
+            merged.hooks.postswitch = ancestor.hooks.postswitch.clone().or(merged.hooks.postswitch);
+            merged.mirror_fallback = ancestor.mirror_fallback.or(merged.mirror_fallback);
+            merged.unlisted_outputs = ancestor.unlisted_outputs.or(merged.unlisted_outputs);
+            merged.conditions = ancestor.conditions.clone().or(merged.conditions);
+        }
+        Ok(merged)
+    }
+
+    /// Fill in the `edid` and `match` of outputs that refer to a known monitor by alias.
+    pub fn resolve_monitors(&mut self, monitors: &[Monitor]) -> Result<()> {
+        for output in self.outputs.values_mut() {
+            let alias = match &output.monitor {
+                Some(alias) => alias,
+                None => continue,
+            };
+            let monitor = monitors
+                .iter()
+                .find(|m| m.name() == alias)
+                .ok_or_else(|| Error::UnknownMonitor(self.name.clone(), alias.clone()))?;
+            if output.edid.is_none() {
+                output.edid = monitor.edid.clone();
+            }
+            if output.matches.is_none() {
+                output.matches = monitor.matches.clone();
+            }
+        }
+        Ok(())
+    }
 }
 
 // private methods
 impl Profile {
-    fn init_set(&mut self) {
-        for (_, output) in &self.outputs {
-            if let Some(edid) = &output.edid {
-                self.set.insert(edid.clone());
-            }
+    pub(crate) fn new(name: &str, outputs: BTreeMap<String, Output>) -> Profile {
+        Profile {
+            outputs,
+            profile_name: None,
+            extends: None,
+            priority: None,
+            hooks: Hooks::default(),
+            mirror_fallback: None,
+            unlisted_outputs: None,
+            conditions: None,
+            name: name.into(),
         }
     }
 }
@@ -50,14 +168,16 @@ impl TryFrom<fs::DirEntry> for Profile {
     type Error = Error;
 
     fn try_from(de: fs::DirEntry) -> Result<Profile> {
-        let path: PathBuf = de.path().into();
+        let path: PathBuf = de.path();
         match path.extension() {
             Some(ext) if ext == "yaml" || ext == "yml" => {
                 let mut file = fs::File::open(&path)?;
                 let mut contents = String::new();
                 let _ = file.read_to_string(&mut contents)?;
                 let mut p: Self = serde_yaml::from_str(&contents)?;
-                p.init_set();
+                for output in p.outputs.values_mut() {
+                    output.lift_xrandr_args()?;
+                }
                 if let Some(ref s) = p.profile_name {
                     p.name = s.clone();
                 } else if let Some(s) = path.file_stem() {
@@ -70,6 +190,58 @@ impl TryFrom<fs::DirEntry> for Profile {
     }
 }
 
+/// A known monitor: a human alias for an EDID or EDID match that profiles can refer to with
+/// `monitor: <alias>` instead of repeating the EDID.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Monitor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) alias: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) edid: Option<Edids>,
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub(crate) matches: Option<EdidMatch>,
+
+    #[serde(skip)]
+    name: String,
+}
+
+impl Monitor {
+    /// The alias, taken from the `alias` field or else the file name.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Whether this is the monitor attached to the detected output.
+    pub fn matches(&self, detected: &Output) -> bool {
+        matcher::identifies(self.edid.as_ref(), self.matches.as_ref(), detected)
+    }
+}
+
+impl TryFrom<fs::DirEntry> for Monitor {
+    type Error = Error;
+
+    fn try_from(de: fs::DirEntry) -> Result<Monitor> {
+        let path: PathBuf = de.path();
+        match path.extension() {
+            Some(ext) if ext == "yaml" || ext == "yml" => {
+                let contents = fs::read_to_string(&path)?;
+                let mut m: Self = serde_yaml::from_str(&contents)?;
+                if m.edid.is_none() && m.matches.is_none() {
+                    return Err(Error::UnrecognizedMonitorConfigFile(path));
+                }
+                if let Some(ref s) = m.alias {
+                    m.name = s.clone();
+                } else if let Some(s) = path.file_stem() {
+                    m.name = s.to_string_lossy().to_string();
+                }
+                Ok(m)
+            }
+            _ => Err(Error::UnrecognizedMonitorConfigFile(path)),
+        }
+    }
+}
+
 impl Ord for Profile {
     fn cmp(&self, other: &Self) -> Ordering {
         self.name.cmp(&other.name)
@@ -84,37 +256,67 @@ impl PartialOrd for Profile {
 
 impl fmt::Display for Profile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}\n", self.name)?;
+        writeln!(f, "{}", self.name)?;
         for (name, output) in &self.outputs {
-            write!(f, "{}\n", name)?;
+            writeln!(f, "{}", name)?;
             write!(f, "{}", output)?;
         }
         Ok(())
     }
 }
 
-/// Config contains profiles (in order of preference) and known outputs.
+/// Global settings, read from `config.yaml` in the configuration directory.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// What to do when no profile matches the detected outputs.
+    #[serde(default)]
+    pub fallback: FallbackPolicy,
+}
+
+impl Settings {
+    pub(crate) fn load(path: &Path) -> Result<Settings> {
+        if !path.exists() {
+            return Ok(Settings::default());
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_yaml::from_str::<Option<Settings>>(&contents)?.unwrap_or_default())
+    }
+}
+
+/// Config contains global settings, profiles, and known monitors.
 pub struct Config {
+    pub settings: Settings,
     pub profiles: Vec<Profile>,
+    pub monitors: Vec<Monitor>,
 }
 
 impl Config {
     pub fn load() -> Result<Config> {
-        let mut profiles = fs::read_dir(Config::profiles_dir()?)?
-            .filter_map(|entry| match entry {
-                Ok(entry) => {
-                    let path = entry.path();
-                    let path_str = path.to_string_lossy();
-                    log::debug!("loading {}", &path_str);
-                    match entry.try_into() {
-                        Ok(e) => Some(e),
-                        Err(e) => {
-                            log::warn!("failed to load {}:\n{:?}", &path_str, e);
-                            None
-                        }
+        let settings = Settings::load(&Config::dir()?.join("config.yaml"))?;
+        log::debug!("fallback policy: {}", settings.fallback);
+
+        let monitors = load_dir::<Monitor>(&Config::monitors_dir()?)?;
+        log::debug!("monitors loaded:");
+        for monitor in &monitors {
+            log::debug!("  {0}", monitor.name);
+        }
+
+        let raw = load_dir::<Profile>(&Config::profiles_dir()?)?;
+        let mut profiles = raw
+            .iter()
+            .filter_map(|p| {
+                let resolved = p.inherit(&raw).and_then(|mut p| {
+                    p.resolve_monitors(&monitors)?;
+                    Ok(p)
+                });
+                match resolved {
+                    Ok(p) => Some(p),
+                    Err(e) => {
+                        log::warn!("failed to load profile {}: {} (run `autorandr validate` for details)", p.name, e);
+                        None
                     }
                 }
-                _ => None,
             })
             .collect::<Vec<Profile>>();
 
@@ -124,16 +326,170 @@ impl Config {
             log::debug!("  {0}", profile.name);
         }
 
-        Ok(Config { profiles })
+        Ok(Config {
+            settings,
+            profiles,
+            monitors,
+        })
     }
 
-    fn profiles_dir() -> Result<PathBuf> {
-        let dir = config_dir()
+    /// Write `profile` to `<name>.yaml` in the profiles directory, refusing to replace an existing
+    /// file unless `force` is set.
+    pub fn save_profile(profile: &Profile, force: bool) -> Result<PathBuf> {
+        let name = profile.name();
+        if name.is_empty() || name.contains(std::path::is_separator) || name.starts_with('.') {
+            return Err(Error::InvalidProfileName(name.into()));
+        }
+
+        let path = Config::profiles_dir()?.join(format!("{}.yaml", name));
+        if path.exists() && !force {
+            return Err(Error::ProfileAlreadyExists(path));
+        }
+
+        fs::write(&path, serde_yaml::to_string(profile)?)?;
+        Ok(path)
+    }
+
+    /// Look up a loaded profile by name.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name() == name)
+    }
+
+    /// Look up the known monitor attached to a detected output.
+    pub fn monitor_for(&self, detected: &Output) -> Option<&Monitor> {
+        self.monitors.iter().find(|m| m.matches(detected))
+    }
+
+    /// The autorandr configuration directory, which holds global hooks alongside `profiles/` and
+    /// `monitors/`.
+    pub fn dir() -> Result<PathBuf> {
+        Ok(config_dir()
             .ok_or(Error::CannotDetermineConfigDir)?
-            .join("autorandr")
-            .join("profiles");
+            .join("autorandr"))
+    }
+
+    fn profiles_dir() -> Result<PathBuf> {
+        let dir = Config::dir()?.join("profiles");
+        fs::create_dir_all(&dir)?;
+
+        Ok(dir)
+    }
+
+    fn monitors_dir() -> Result<PathBuf> {
+        let dir = Config::dir()?.join("monitors");
         fs::create_dir_all(&dir)?;
 
         Ok(dir)
     }
 }
+
+/// Load every entry of `dir`, logging and skipping those that fail to load.
+fn load_dir<T>(dir: &Path) -> Result<Vec<T>>
+where
+    T: TryFrom<fs::DirEntry, Error = Error>,
+{
+    Ok(fs::read_dir(dir)?
+        .filter_map(|entry| match entry {
+            Ok(entry) => {
+                let path = entry.path();
+                let path_str = path.to_string_lossy();
+                log::debug!("loading {}", &path_str);
+                match entry.try_into() {
+                    Ok(e) => Some(e),
+                    Err(e) => {
+                        log::warn!("failed to load {}:\n{:?}", &path_str, e);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_monitor_aliases() {
+        let mut monitor: Monitor =
+            serde_yaml::from_str("match:\n  vendor: DEL\n  name: \"DELL U27*\"\n").unwrap();
+        monitor.name = "office-left".into();
+
+        let mut profile: Profile = serde_yaml::from_str(
+            r#"
+outputs:
+  left:
+    monitor: office-left
+    xrandr_args:
+      --pos: 0x0
+  laptop:
+    edid: "00ff"
+"#,
+        )
+        .unwrap();
+        profile.resolve_monitors(&[monitor.clone()]).unwrap();
+        assert_eq!(profile.outputs["left"].matches, monitor.matches);
+        assert_eq!(profile.outputs["laptop"].matches, None);
+
+        profile.outputs.get_mut("laptop").unwrap().monitor = Some("office-right".into());
+        assert!(matches!(
+            profile.resolve_monitors(&[monitor]),
+            Err(Error::UnknownMonitor(_, alias)) if alias == "office-right"
+        ));
+    }
+
+    fn named(name: &str, yaml: &str) -> Profile {
+        let mut p: Profile = serde_yaml::from_str(yaml).unwrap();
+        p.name = name.into();
+        p
+    }
+
+    #[test]
+    fn inherit_outputs_through_extends_chain() {
+        let profiles = vec![
+            named(
+                "laptop",
+                "priority: 1\noutputs:\n  panel:\n    edid: aa\n    xrandr_args:\n      --mode: 1920x1080\n",
+            ),
+            named(
+                "desk",
+                "extends: laptop\nhooks:\n  postswitch: polybar\noutputs:\n  left:\n    edid: bb\n",
+            ),
+            named(
+                "desk-projector",
+                "extends: desk\npriority: 2\noutputs:\n  left:\n    edid: cc\n  projector:\n    edid: dd\n",
+            ),
+        ];
+
+        let merged = profiles[2].inherit(&profiles).unwrap();
+        assert_eq!(merged.name(), "desk-projector");
+        assert_eq!(merged.priority(), 2);
+        assert_eq!(profiles[1].inherit(&profiles).unwrap().priority(), 1);
+        assert_eq!(merged.hooks.postswitch.as_deref(), Some("polybar"));
+        assert_eq!(
+            merged.outputs.keys().collect::<Vec<&String>>(),
+            ["left", "panel", "projector"]
+        );
+        assert_eq!(merged.outputs["left"].edid, Some(Edids::One("cc".into())));
+        assert_eq!(merged.outputs["panel"], profiles[0].outputs["panel"]);
+    }
+
+    #[test]
+    fn reject_missing_parents_and_cycles() {
+        let profiles = vec![
+            named("a", "extends: b\noutputs: {}\n"),
+            named("b", "extends: a\noutputs: {}\n"),
+            named("c", "extends: nope\noutputs: {}\n"),
+        ];
+        assert!(matches!(
+            profiles[0].inherit(&profiles),
+            Err(Error::ProfileInheritanceCycle(chain)) if chain == "a -> b -> a"
+        ));
+        assert!(matches!(
+            profiles[2].inherit(&profiles),
+            Err(Error::MissingParentProfile(child, parent)) if child == "c" && parent == "nope"
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dirs::runtime_dir;
+
+use crate::{Error, Result};
+
+/// How often to check whether a layout has been confirmed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A layout waiting to be confirmed, marked by a file that `confirm` removes.
+pub(crate) struct Pending {
+    path: PathBuf,
+}
+
+impl Pending {
+    pub(crate) fn create() -> Result<Pending> {
+        Pending::at(marker()?)
+    }
+
+    fn at(path: PathBuf) -> Result<Pending> {
+        fs::write(&path, process::id().to_string())?;
+        Ok(Pending { path })
+    }
+
+    /// Wait up to `timeout` for the layout to be confirmed, either by `autorandr confirm` or, when
+    /// run from a terminal, by pressing Enter. Returns whether it was confirmed.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let (tx, rx) = mpsc::channel();
+        if io::stdin().is_terminal() {
+            log::info!(
+                "press Enter or run `autorandr confirm` within {}s to keep this layout",
+                timeout.as_secs()
+            );
+            // the thread is left blocked on stdin if nobody presses Enter
+            thread::spawn(move || {
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line).is_ok() {
+                    let _ = tx.send(());
+                }
+            });
+        } else {
+            log::info!("run `autorandr confirm` within {}s to keep this layout", timeout.as_secs());
+        }
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if !self.path.exists() || rx.try_recv().is_ok() {
+                return true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        false
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Confirm the layout that another autorandr process is waiting on.
+pub fn confirm() -> Result<()> {
+    confirm_at(&marker()?)
+}
+
+fn confirm_at(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(Error::NothingToConfirm);
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// The marker for the X display in `$DISPLAY`, so that sessions on different displays don't
+/// confirm each other's layouts.
+fn marker() -> Result<PathBuf> {
+    let dir = runtime_dir().unwrap_or_else(std::env::temp_dir).join("autorandr");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(marker_name(std::env::var("DISPLAY").ok().as_deref())))
+}
+
+fn marker_name(display: Option<&str>) -> String {
+    match display {
+        Some(display) => format!("pending-confirmation-{}", display.replace('/', "_")),
+        None => "pending-confirmation".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TempDir;
+
+    #[test]
+    fn confirm_pending_layout() {
+        let dir = TempDir::new("confirm");
+        let path = dir.join("pending-confirmation");
+
+        let pending = Pending::at(path.clone()).unwrap();
+        assert!(!pending.wait(Duration::from_millis(250)));
+
+        let confirmer = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(200));
+                confirm_at(&path)
+            })
+        };
+        assert!(pending.wait(Duration::from_secs(5)));
+        confirmer.join().unwrap().unwrap();
+
+        drop(pending);
+        assert!(!path.exists());
+        assert!(matches!(confirm_at(&path), Err(Error::NothingToConfirm)));
+    }
+
+    #[test]
+    fn marker_per_display() {
+        assert_eq!(marker_name(Some(":0")), "pending-confirmation-:0");
+        assert_eq!(
+            marker_name(Some("/tmp/launch/org.xquartz:0")),
+            "pending-confirmation-_tmp_launch_org.xquartz:0"
+        );
+        assert_eq!(marker_name(None), "pending-confirmation");
+    }
+}
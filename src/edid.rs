@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::{Error, Result};
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const BLOCK_LEN: usize = 128;
+const DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+
+const TAG_SERIAL: u8 = 0xff;
+const TAG_NAME: u8 = 0xfc;
+
+/// A detailed timing descriptor, as found in the first descriptor slot of most EDIDs where it
+/// describes the preferred (native) mode.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Timing {
+    /// Pixel clock in kHz.
+    pub pixel_clock: u32,
+    pub width: u16,
+    pub height: u16,
+    pub h_blank: u16,
+    pub v_blank: u16,
+}
+
+impl Timing {
+    /// Vertical refresh rate in Hz.
+    pub fn refresh_rate(&self) -> f64 {
+        let total = (self.width as f64 + self.h_blank as f64) * (self.height as f64 + self.v_blank as f64);
+        if total == 0.0 {
+            return 0.0;
+        }
+        self.pixel_clock as f64 * 1000.0 / total
+    }
+}
+
+/// The identifying fields of an EDID base block.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Edid {
+    /// Three-letter PNP manufacturer ID, eg "DEL".
+    pub manufacturer: String,
+    pub product_code: u16,
+    /// Numeric serial number; zero when the monitor doesn't report one.
+    pub serial: u32,
+    /// Serial number from the display serial descriptor, if present.
+    pub serial_string: Option<String>,
+    /// Monitor name from the display name descriptor, if present.
+    pub name: Option<String>,
+    pub manufacture_week: u8,
+    pub manufacture_year: u16,
+    /// Physical width in centimeters; zero for projectors and other variable-size displays.
+    pub width_cm: u8,
+    /// Physical height in centimeters; zero for projectors and other variable-size displays.
+    pub height_cm: u8,
+    pub preferred_timing: Option<Timing>,
+}
+
+impl Edid {
+    /// Decode an EDID from its hex representation, as stored in `Output.edid`.
+    pub fn from_hex(s: &str) -> Result<Edid> {
+        Edid::parse(&hex::decode(s)?)
+    }
+
+    /// Decode the base block of a raw EDID blob. A bad checksum only earns a warning, since some
+    /// monitors and KVMs get it wrong while the fields themselves are fine.
+    pub fn parse(bytes: &[u8]) -> Result<Edid> {
+        if bytes.len() < BLOCK_LEN {
+            return Err(Error::InvalidEdid(format!(
+                "expected at least {} bytes, got {}",
+                BLOCK_LEN,
+                bytes.len()
+            )));
+        }
+        let block = &bytes[..BLOCK_LEN];
+        if block[..8] != HEADER {
+            return Err(Error::InvalidEdid("bad header".into()));
+        }
+        if block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            log::warn!("EDID has a bad checksum; decoding it anyway");
+        }
+
+        let id = u16::from_be_bytes([block[8], block[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| (b'A' - 1 + ((id >> shift) & 0x1f) as u8) as char)
+            .collect();
+
+        let mut edid = Edid {
+            manufacturer,
+            product_code: u16::from_le_bytes([block[10], block[11]]),
+            serial: u32::from_le_bytes([block[12], block[13], block[14], block[15]]),
+            serial_string: None,
+            name: None,
+            manufacture_week: block[16],
+            manufacture_year: 1990 + block[17] as u16,
+            width_cm: block[21],
+            height_cm: block[22],
+            preferred_timing: None,
+        };
+
+        for (i, offset) in DESCRIPTORS.iter().enumerate() {
+            let d = &block[*offset..*offset + 18];
+            if d[0] != 0 || d[1] != 0 {
+                if i == 0 {
+                    edid.preferred_timing = Some(parse_timing(d));
+                }
+                continue;
+            }
+            match d[3] {
+                TAG_NAME => edid.name = parse_text(d),
+                TAG_SERIAL => edid.serial_string = parse_text(d),
+                _ => (),
+            }
+        }
+
+        Ok(edid)
+    }
+}
+
+fn parse_timing(d: &[u8]) -> Timing {
+    Timing {
+        pixel_clock: u16::from_le_bytes([d[0], d[1]]) as u32 * 10,
+        width: d[2] as u16 | ((d[4] as u16 & 0xf0) << 4),
+        h_blank: d[3] as u16 | ((d[4] as u16 & 0x0f) << 8),
+        height: d[5] as u16 | ((d[7] as u16 & 0xf0) << 4),
+        v_blank: d[6] as u16 | ((d[7] as u16 & 0x0f) << 8),
+    }
+}
+
+fn parse_text(d: &[u8]) -> Option<String> {
+    let text = &d[5..18];
+    let end = text.iter().position(|b| *b == 0x0a).unwrap_or(text.len());
+    let s = String::from_utf8_lossy(&text[..end]).trim().to_string();
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+impl fmt::Display for Edid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X}", self.manufacturer, self.product_code)?;
+        if let Some(name) = &self.name {
+            write!(f, " \"{}\"", name)?;
+        }
+        match &self.serial_string {
+            Some(serial) => write!(f, ", serial {}", serial)?,
+            None if self.serial != 0 => write!(f, ", serial {}", self.serial)?,
+            None => (),
+        }
+        write!(f, ", made {} week {}", self.manufacture_year, self.manufacture_week)?;
+        if self.width_cm != 0 && self.height_cm != 0 {
+            write!(f, ", {}x{}cm", self.width_cm, self.height_cm)?;
+        }
+        if let Some(t) = &self.preferred_timing {
+            write!(f, ", {}x{}@{:.2}Hz", t.width, t.height, t.refresh_rate())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn text_descriptor(tag: u8, text: &str) -> [u8; 18] {
+        let mut d = [0u8; 18];
+        d[3] = tag;
+        let mut body = [0x20u8; 13];
+        body[..text.len()].copy_from_slice(text.as_bytes());
+        if text.len() < 13 {
+            body[text.len()] = 0x0a;
+        }
+        d[5..].copy_from_slice(&body);
+        d
+    }
+
+    /// Build a checksummed EDID base block for tests.
+    pub(crate) fn edid_bytes(manufacturer: &str, product: u16, serial: u32, name: &str) -> Vec<u8> {
+        let mut b = vec![0u8; BLOCK_LEN];
+        b[..8].copy_from_slice(&HEADER);
+        let m = manufacturer.as_bytes();
+        let id = m.iter().fold(0u16, |id, c| (id << 5) | (c - b'A' + 1) as u16);
+        b[8..10].copy_from_slice(&id.to_be_bytes());
+        b[10..12].copy_from_slice(&product.to_le_bytes());
+        b[12..16].copy_from_slice(&serial.to_le_bytes());
+        b[16] = 12;
+        b[17] = 31;
+        b[21] = 60;
+        b[22] = 34;
+
+        // 3840x2160@60: 533.25MHz, 160 h blank, 62 v blank
+        b[54..72].copy_from_slice(&[
+            0x4d, 0xd0, 0x00, 0xa0, 0xf0, 0x70, 0x3e, 0x80, 0, 0, 0, 0, 0x54, 0x55, 0x21, 0, 0, 0x1a,
+        ]);
+        b[72..90].copy_from_slice(&text_descriptor(TAG_NAME, name));
+        b[90..108].copy_from_slice(&text_descriptor(TAG_SERIAL, "ABC123"));
+        b[108..126].copy_from_slice(&text_descriptor(0x10, ""));
+
+        let sum = b.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+        b[127] = 0u8.wrapping_sub(sum);
+        b
+    }
+
+    #[test]
+    fn parse_base_block() {
+        let edid = Edid::parse(&edid_bytes("DEL", 0xa0f4, 0x12345678, "DELL U2720Q")).unwrap();
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.product_code, 0xa0f4);
+        assert_eq!(edid.serial, 0x12345678);
+        assert_eq!(edid.serial_string.as_deref(), Some("ABC123"));
+        assert_eq!(edid.name.as_deref(), Some("DELL U2720Q"));
+        assert_eq!((edid.manufacture_week, edid.manufacture_year), (12, 2021));
+        assert_eq!((edid.width_cm, edid.height_cm), (60, 34));
+
+        let timing = edid.preferred_timing.unwrap();
+        assert_eq!((timing.width, timing.height), (3840, 2160));
+        assert!((timing.refresh_rate() - 60.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn reject_corrupt_blocks() {
+        let mut bytes = edid_bytes("DEL", 1, 1, "X");
+        assert!(Edid::parse(&bytes[..100]).is_err());
+
+        // a bad checksum alone still decodes
+        bytes[127] ^= 0xff;
+        assert_eq!(Edid::parse(&bytes).unwrap().manufacturer, "DEL");
+
+        bytes[0] ^= 0xff;
+        assert!(matches!(Edid::parse(&bytes), Err(Error::InvalidEdid(_))));
+    }
+}
@@ -53,17 +53,36 @@ pub enum Error {
     #[error("command '{0}' failed for unknown reasons")]
     SubprocessUnknownFailure(String),
 
+    #[error("invalid EDID: {0}")]
+    InvalidEdid(String),
+
+    /// A pre- or post-switch hook exited non-zero.
+    #[error("{0} hook '{1}' failed with status {2}")]
+    HookFailed(String, String, u32),
+
     #[error("cannot determine configuration directory on this platform")]
     CannotDetermineConfigDir,
 
     #[error("invalid profile data: {0}")]
     UnrecognizedProfileConfigFile(PathBuf),
 
+    #[error("profile already exists: {0} (use --force to overwrite)")]
+    ProfileAlreadyExists(PathBuf),
+
+    #[error("invalid profile name: \"{0}\"")]
+    InvalidProfileName(String),
+
     #[error("invalid monitor data: {0}")]
     UnrecognizedMonitorConfigFile(PathBuf),
 
-    #[error("too many active monitors for mirror mode")]
-    MirrorModeTooManyActiveMonitors,
+    #[error("profile '{0}' extends unknown profile '{1}'")]
+    MissingParentProfile(String, String),
+
+    #[error("profile inheritance cycle: {0}")]
+    ProfileInheritanceCycle(String),
+
+    #[error("profile '{0}' refers to unknown monitor '{1}'")]
+    UnknownMonitor(String, String),
 
     #[error("at least one active monitor required!")]
     NoActiveMonitors,
@@ -73,6 +92,75 @@ pub enum Error {
 
     #[error("monitor '{0}' does not have resolution mode compatible with active monitor")]
     MirrorModeIncompatibleResolution(String),
+
+    #[error("unknown backend \"{0}\" (expected \"native\" or \"xrandr\")")]
+    UnknownBackend(String),
+
+    #[error("cannot open X display")]
+    CannotOpenDisplay,
+
+    #[error("the X server does not support the RandR extension")]
+    RandrUnavailable,
+
+    #[error("no output named '{0}'")]
+    UnknownOutput(String),
+
+    #[error("output '{0}': the native backend can't apply '{1}'; use --backend xrandr")]
+    UnsupportedArgument(String, String),
+
+    #[error("output '{0}': argument '{1}' requires a value")]
+    MissingArgumentValue(String, String),
+
+    #[error("output '{0}': invalid value '{2}' for argument '{1}'")]
+    InvalidArgumentValue(String, String, String),
+
+    #[error("output '{0}' does not support mode '{1}'")]
+    NoSuchMode(String, String),
+
+    #[error("no free crtc available for output '{0}'")]
+    NoCrtcAvailable(String),
+
+    #[error("failed to configure crtc for '{0}' (RRSetCrtcConfig status {1})")]
+    CrtcConfigFailed(String, i32),
+
+    #[error("screen size {0}x{1} exceeds maximum {2}x{3}")]
+    ScreenSizeTooLarge(i32, i32, i32, i32),
+
+    #[error("RandR request failed (major opcode {0}, minor opcode {1}, error code {2})")]
+    RandrRequestFailed(u8, u8, u8),
+
+    #[error("output '{0}' is placed relative to '{1}', which is not part of the layout")]
+    PlacementUnknownOutput(String, String),
+
+    #[error("placement of outputs {0} is circular")]
+    PlacementCycle(String),
+
+    #[error("cannot determine the size of output '{0}'")]
+    UnknownOutputSize(String),
+
+    #[error("outputs '{0}' and '{1}' overlap")]
+    OverlappingOutputs(String, String),
+
+    #[error("output '{0}' does not touch the rest of the layout")]
+    DisconnectedLayout(String),
+
+    #[error("invalid {0} '{1}'")]
+    InvalidSetting(String, String),
+
+    #[error("no profile named '{0}'")]
+    UnknownProfile(String),
+
+    #[error("profile '{0}' does not match the connected monitors")]
+    ProfileDoesNotMatch(String),
+
+    #[error("unsaved layout")]
+    UnsavedLayout,
+
+    #[error("no layout is waiting to be confirmed")]
+    NothingToConfirm,
+
+    #[error("layout was not confirmed within {0}s and has been reverted")]
+    NotConfirmed(u64),
 }
 
 impl From<Infallible> for Error {
@@ -0,0 +1,375 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::largest_common_resolution;
+use crate::{Error, Layout, MirrorFallback, Output, Result};
+
+/// What `reconcile` does with the detected outputs when no profile matches them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackPolicy {
+    /// Place the other outputs side by side to the right of the primary output.
+    #[default]
+    ExtendRight,
+    /// Place the other outputs side by side to the left of the primary output.
+    ExtendLeft,
+    /// Mirror the primary output onto all the others.
+    Mirror,
+    /// Turn off built-in panels and extend across the external outputs, if there are any.
+    ExternalOnly,
+    /// Leave connected outputs as they are.
+    None,
+}
+
+impl FallbackPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FallbackPolicy::ExtendRight => "extend-right",
+            FallbackPolicy::ExtendLeft => "extend-left",
+            FallbackPolicy::Mirror => "mirror",
+            FallbackPolicy::ExternalOnly => "external-only",
+            FallbackPolicy::None => "none",
+        }
+    }
+
+    /// Add the settings for `outputs` called for by this policy to `layout`.
+    pub(crate) fn arrange(&self, outputs: &[&Output], layout: &mut Layout) -> Result<()> {
+        let mut outputs = outputs.to_vec();
+        outputs.sort_by_key(|o| (!o.settings.primary, !is_internal(o), o.output_name.clone()));
+        if outputs.is_empty() {
+            return Ok(());
+        }
+
+        match self {
+            FallbackPolicy::ExtendRight => extend(&outputs, Some(outputs[0]), 0, layout),
+            FallbackPolicy::ExtendLeft => {
+                // the primary output ends up rightmost, with the others in name order to its left
+                let primary = outputs[0];
+                outputs.rotate_left(1);
+                extend(&outputs, Some(primary), 0, layout);
+            }
+            FallbackPolicy::Mirror => mirror(&outputs, &[], MirrorFallback::Scale, layout)?,
+            FallbackPolicy::ExternalOnly => {
+                let (internal, external): (Vec<&Output>, Vec<&Output>) =
+                    outputs.iter().partition(|o| is_internal(o));
+                if external.is_empty() {
+                    extend(&internal, Some(internal[0]), 0, layout);
+                } else {
+                    for output in internal {
+                        layout.off(output.output_name.as_ref().unwrap());
+                    }
+                    extend(&external, Some(external[0]), 0, layout);
+                }
+            }
+            FallbackPolicy::None => {}
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FallbackPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What a profile does with connected outputs that it doesn't list.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnlistedOutputs {
+    /// Turn them off.
+    #[default]
+    Off,
+    /// Leave them as they are.
+    Keep,
+    /// Place them side by side to the right of the profile's outputs, at their preferred modes.
+    AutoExtend,
+    /// Show the profile's primary output on them.
+    MirrorPrimary,
+}
+
+impl UnlistedOutputs {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnlistedOutputs::Off => "off",
+            UnlistedOutputs::Keep => "keep",
+            UnlistedOutputs::AutoExtend => "auto-extend",
+            UnlistedOutputs::MirrorPrimary => "mirror-primary",
+        }
+    }
+
+    /// Add the settings for the `unlisted` outputs called for by this policy to `layout`, given
+    /// the `areas` (x, y, width, height) covered by the profile's lit outputs and the name of the
+    /// profile's primary output among them.
+    pub(crate) fn arrange(
+        &self,
+        unlisted: &[&Output],
+        areas: &BTreeMap<String, (i32, i32, i32, i32)>,
+        primary: Option<&str>,
+        layout: &mut Layout,
+    ) {
+        let mut unlisted = unlisted.to_vec();
+        unlisted.sort_by_key(|o| o.output_name.clone());
+        let right_edge = areas.values().map(|(x, _, w, _)| x + w).max().unwrap_or(0);
+
+        match self {
+            UnlistedOutputs::Off => {
+                for output in unlisted {
+                    layout.off(output.output_name.as_ref().unwrap());
+                }
+            }
+            UnlistedOutputs::Keep => {}
+            UnlistedOutputs::AutoExtend => extend(&unlisted, None, right_edge.max(0) as u32, layout),
+            UnlistedOutputs::MirrorPrimary => {
+                let area = primary
+                    .and_then(|name| areas.get(name))
+                    .or_else(|| areas.values().next());
+                let (x, y, w, h) = match area {
+                    Some(area) => *area,
+                    None => return extend(&unlisted, None, 0, layout),
+                };
+                for output in unlisted {
+                    let name = output.output_name.as_ref().unwrap();
+                    let mut args = match output.mode_with_resolution(w as u32, h as u32) {
+                        Some(mode) => vec!["--mode".into(), mode.name.clone()],
+                        None => match output.preferred_mode() {
+                            Some(mode) => vec![
+                                "--mode".into(),
+                                mode.name.clone(),
+                                "--scale-from".into(),
+                                format!("{}x{}", w, h),
+                            ],
+                            None => {
+                                log::warn!("no modes known for {}; mirroring it with --auto", name);
+                                vec!["--auto".into()]
+                            }
+                        },
+                    };
+                    args.extend(["--pos".into(), format!("{}x{}", x, y)]);
+                    layout.set(name, args);
+                }
+            }
+        }
+    }
+}
+
+/// Whether the output is a built-in panel, judging by its connector name.
+fn is_internal(output: &Output) -> bool {
+    let name = output.output_name.as_deref().unwrap_or("").to_lowercase();
+    ["edp", "lvds", "dsi"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// Place `outputs` left to right from `x` at their preferred modes, top-aligned.
+fn extend(outputs: &[&Output], primary: Option<&Output>, mut x: u32, layout: &mut Layout) {
+    for output in outputs {
+        let name = output.output_name.as_ref().unwrap();
+        let mut args = match output.preferred_mode() {
+            Some(mode) => {
+                let args = vec!["--mode".into(), mode.name.clone(), "--pos".into(), format!("{}x0", x)];
+                x += mode.width;
+                args
+            }
+            None => {
+                log::warn!("no modes known for {}; placing it with --auto", name);
+                vec!["--auto".into(), "--pos".into(), format!("{}x0", x)]
+            }
+        };
+        if primary.is_some_and(|p| p.output_name == output.output_name) {
+            args.push("--primary".into());
+        }
+        layout.set(name, args);
+    }
+}
+
+/// Show the first of `mirrored` on all of them: at the largest resolution they have in common,
+/// or else, with `MirrorFallback::Scale`, by scaling the others from the first one's current
+/// (or preferred) mode. `base_args` are added to every output.
+pub(crate) fn mirror(
+    mirrored: &[&Output],
+    base_args: &[String],
+    fallback: MirrorFallback,
+    layout: &mut Layout,
+) -> Result<()> {
+    let (source, others) = match mirrored.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    if let Some((width, height)) = largest_common_resolution(mirrored) {
+        log::debug!("mirroring at common resolution {}x{}", width, height);
+        for output in mirrored {
+            let mode = output.mode_with_resolution(width, height).unwrap();
+            let mut args = vec!["--mode".into(), mode.name.clone(), "--pos".into(), "0x0".into()];
+            args.extend(base_args.iter().cloned());
+            layout.set(output.output_name.as_ref().unwrap(), args);
+        }
+        return Ok(());
+    }
+
+    let mode = source
+        .settings
+        .mode
+        .as_ref()
+        .and_then(|name| source.modes.iter().find(|m| m.name == *name))
+        .or_else(|| source.preferred_mode())
+        .ok_or_else(|| Error::MirrorModeIncompatibleResolution(name_of(source)))?;
+    if fallback == MirrorFallback::Fail {
+        let incompatible = others
+            .iter()
+            .find(|o| o.mode_with_resolution(mode.width, mode.height).is_none())
+            .unwrap_or(source);
+        return Err(Error::MirrorModeIncompatibleResolution(name_of(incompatible)));
+    }
+
+    log::debug!("no common resolution; scaling from {}x{}", mode.width, mode.height);
+    let mut args = vec!["--mode".into(), mode.name.clone(), "--pos".into(), "0x0".into()];
+    args.extend(base_args.iter().cloned());
+    layout.set(source.output_name.as_ref().unwrap(), args);
+    for output in others {
+        let preferred = output
+            .preferred_mode()
+            .ok_or_else(|| Error::MirrorModeIncompatibleResolution(name_of(output)))?;
+        let mut args = vec![
+            "--mode".into(),
+            preferred.name.clone(),
+            "--pos".into(),
+            "0x0".into(),
+            "--scale-from".into(),
+            format!("{}x{}", mode.width, mode.height),
+        ];
+        args.extend(base_args.iter().cloned());
+        layout.set(output.output_name.as_ref().unwrap(), args);
+    }
+    Ok(())
+}
+
+fn name_of(output: &Output) -> String {
+    output.output_name.clone().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::OutputMode;
+
+    fn output(name: &str, width: u32, height: u32) -> Output {
+        Output {
+            output_name: Some(name.into()),
+            modes: vec![OutputMode {
+                name: format!("{}x{}", width, height),
+                width,
+                height,
+                refresh: 60000,
+                preferred: true,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn arranged(policy: FallbackPolicy, outputs: &[&Output]) -> Vec<(String, String)> {
+        let mut layout = Layout::default();
+        policy.arrange(outputs, &mut layout).unwrap();
+        layout
+            .outputs
+            .into_iter()
+            .map(|o| (o.name, o.args.join(" ")))
+            .collect()
+    }
+
+    #[test]
+    fn extend_from_preferred_modes() {
+        let laptop = output("eDP-1", 1920, 1200);
+        let left = output("DP-1", 2560, 1440);
+        let right = output("HDMI-1", 3840, 2160);
+        let outputs = [&right, &left, &laptop];
+
+        assert_eq!(
+            arranged(FallbackPolicy::ExtendRight, &outputs),
+            [
+                ("eDP-1".into(), "--mode 1920x1200 --pos 0x0 --primary".into()),
+                ("DP-1".into(), "--mode 2560x1440 --pos 1920x0".into()),
+                ("HDMI-1".into(), "--mode 3840x2160 --pos 4480x0".into()),
+            ]
+        );
+        assert_eq!(
+            arranged(FallbackPolicy::ExtendLeft, &outputs),
+            [
+                ("DP-1".into(), "--mode 2560x1440 --pos 0x0".into()),
+                ("HDMI-1".into(), "--mode 3840x2160 --pos 2560x0".into()),
+                ("eDP-1".into(), "--mode 1920x1200 --pos 6400x0 --primary".into()),
+            ]
+        );
+        assert_eq!(
+            arranged(FallbackPolicy::ExternalOnly, &outputs),
+            [
+                ("eDP-1".into(), "--off".into()),
+                ("DP-1".into(), "--mode 2560x1440 --pos 0x0 --primary".into()),
+                ("HDMI-1".into(), "--mode 3840x2160 --pos 2560x0".into()),
+            ]
+        );
+        assert_eq!(
+            arranged(FallbackPolicy::ExternalOnly, &[&laptop]),
+            [("eDP-1".into(), "--mode 1920x1200 --pos 0x0 --primary".into())]
+        );
+        assert!(arranged(FallbackPolicy::None, &outputs).is_empty());
+    }
+
+    #[test]
+    fn arrange_unlisted_outputs() {
+        let projector = output("HDMI-1", 1024, 768);
+        let tv = output("DP-2", 1920, 1080);
+        let areas = BTreeMap::from([
+            ("laptop".to_string(), (0, 0, 1920, 1080)),
+            ("desk".to_string(), (1920, 0, 2560, 1440)),
+        ]);
+        let arranged = |policy: UnlistedOutputs| {
+            let mut layout = Layout::default();
+            policy.arrange(&[&projector, &tv], &areas, Some("desk"), &mut layout);
+            layout
+                .outputs
+                .into_iter()
+                .map(|o| format!("{} {}", o.name, o.args.join(" ")))
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(arranged(UnlistedOutputs::Off), ["DP-2 --off", "HDMI-1 --off"]);
+        assert!(arranged(UnlistedOutputs::Keep).is_empty());
+        assert_eq!(
+            arranged(UnlistedOutputs::AutoExtend),
+            [
+                "DP-2 --mode 1920x1080 --pos 4480x0",
+                "HDMI-1 --mode 1024x768 --pos 6400x0",
+            ]
+        );
+        assert_eq!(
+            arranged(UnlistedOutputs::MirrorPrimary),
+            [
+                "DP-2 --mode 1920x1080 --scale-from 2560x1440 --pos 1920x0",
+                "HDMI-1 --mode 1024x768 --scale-from 2560x1440 --pos 1920x0",
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_scales_without_common_resolution() {
+        let laptop = output("eDP-1", 1920, 1200);
+        let projector = output("HDMI-1", 1024, 768);
+
+        assert_eq!(
+            arranged(FallbackPolicy::Mirror, &[&projector, &laptop]),
+            [
+                ("eDP-1".into(), "--mode 1920x1200 --pos 0x0".into()),
+                ("HDMI-1".into(), "--mode 1024x768 --pos 0x0 --scale-from 1920x1200".into()),
+            ]
+        );
+
+        let mut layout = Layout::default();
+        assert!(matches!(
+            mirror(&[&laptop, &projector], &[], MirrorFallback::Fail, &mut layout),
+            Err(Error::MirrorModeIncompatibleResolution(name)) if name == "HDMI-1"
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use subprocess::{Exec, ExitStatus};
+
+use crate::{Error, Result};
+
+/// When a hook runs relative to applying a layout.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    Preswitch,
+    Postswitch,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Preswitch => "preswitch",
+            Stage::Postswitch => "postswitch",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Shell commands a profile runs around applying its layout.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preswitch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub postswitch: Option<String>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.preswitch.is_none() && self.postswitch.is_none()
+    }
+
+    fn get(&self, stage: Stage) -> Option<&String> {
+        match stage {
+            Stage::Preswitch => self.preswitch.as_ref(),
+            Stage::Postswitch => self.postswitch.as_ref(),
+        }
+    }
+}
+
+/// Run the global hook for `stage` (an executable named after the stage in `global_dir`, if
+/// present) followed by the profile's hook, stopping at the first failure.
+pub(crate) fn run(
+    stage: Stage,
+    global_dir: &Path,
+    profile: Option<&Hooks>,
+    env: &[(String, String)],
+) -> Result<()> {
+    let global = global_dir.join(stage.name());
+    if global.is_file() {
+        let label = global.to_string_lossy().to_string();
+        run_one(stage, &label, Exec::cmd(&global), env)?;
+    }
+
+    if let Some(cmd) = profile.and_then(|h| h.get(stage)) {
+        run_one(stage, cmd, Exec::shell(cmd), env)?;
+    }
+    Ok(())
+}
+
+fn run_one(stage: Stage, label: &str, mut exec: Exec, env: &[(String, String)]) -> Result<()> {
+    log::debug!("running {} hook '{}'", stage, label);
+    exec = exec.env("AUTORANDR_HOOK", stage.name());
+    for (key, value) in env {
+        exec = exec.env(key, value);
+    }
+    match exec.join()? {
+        ExitStatus::Exited(0) => Ok(()),
+        ExitStatus::Exited(s) => Err(Error::HookFailed(stage.to_string(), label.into(), s)),
+        ExitStatus::Signaled(s) => Err(Error::SubprocessKilledBySignal(label.into(), s)),
+        _ => Err(Error::SubprocessUnknownFailure(label.into())),
+    }
+}
+
+/// Environment variables describing a switch to `profile`: `AUTORANDR_PROFILE`, `AUTORANDR_OUTPUTS`
+/// (space-separated `output=edid` pairs), and `AUTORANDR_EDID_<OUTPUT>` for each output, with
+/// non-alphanumeric characters in the output name replaced by `_`.
+pub(crate) fn env<'a, I>(profile: Option<&str>, outputs: I) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let outputs = outputs.into_iter().collect::<Vec<(&str, &str)>>();
+    let mut env = vec![
+        ("AUTORANDR_PROFILE".to_string(), profile.unwrap_or("").to_string()),
+        (
+            "AUTORANDR_OUTPUTS".to_string(),
+            outputs
+                .iter()
+                .map(|(name, edid)| format!("{}={}", name, edid))
+                .collect::<Vec<String>>()
+                .join(" "),
+        ),
+    ];
+    for (name, edid) in outputs {
+        let key = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect::<String>();
+        env.push((format!("AUTORANDR_EDID_{}", key), edid.to_string()));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testing::TempDir;
+
+    #[test]
+    fn run_global_and_profile_hooks() {
+        let dir = TempDir::new("hooks");
+        let out = dir.join("out");
+        let env = env(Some("desk"), [("DP-1", "00ff")]);
+
+        let hooks = Hooks {
+            preswitch: Some(format!(
+                "echo \"$AUTORANDR_HOOK $AUTORANDR_PROFILE $AUTORANDR_OUTPUTS $AUTORANDR_EDID_DP_1\" > {}",
+                out.display()
+            )),
+            postswitch: Some("exit 3".into()),
+        };
+        run(Stage::Preswitch, &dir, Some(&hooks), &env).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "preswitch desk DP-1=00ff 00ff\n");
+
+        match run(Stage::Postswitch, &dir, Some(&hooks), &env) {
+            Err(Error::HookFailed(stage, _, 3)) => assert_eq!(stage, "postswitch"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
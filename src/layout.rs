@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+use subprocess::{Exec, Redirection};
+
+use crate::Error;
+
+/// The mechanism used to apply a `Layout` to the X server.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Backend {
+    /// Issue RandR requests directly over the X connection.
+    #[default]
+    Native,
+    /// Shell out to the `xrandr` binary.
+    Xrandr,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Backend::Native),
+            "xrandr" => Ok(Backend::Xrandr),
+            _ => Err(Error::UnknownBackend(s.into())),
+        }
+    }
+}
+
+/// Desired settings for a single output, expressed as `xrandr --output` arguments.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutputLayout {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// The set of output changes to apply in a single step. Outputs not mentioned are left as they
+/// are.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Layout {
+    /// Name of the profile this layout was built from, if any.
+    pub profile: Option<String>,
+    pub outputs: Vec<OutputLayout>,
+}
+
+impl Layout {
+    /// Turn the named output off.
+    pub fn off(&mut self, name: &str) {
+        self.set(name, vec!["--off".into()]);
+    }
+
+    /// Configure the named output with the given `xrandr --output` arguments.
+    pub fn set(&mut self, name: &str, args: Vec<String>) {
+        self.outputs.push(OutputLayout {
+            name: name.into(),
+            args,
+        });
+    }
+
+    /// The `xrandr` invocation equivalent to this layout.
+    pub fn xrandr_command(&self) -> Exec {
+        let mut cmd = Exec::cmd("xrandr").stderr(Redirection::Merge);
+        for output in &self.outputs {
+            cmd = cmd.arg("--output").arg(&output.name).args(&output.args);
+        }
+        cmd
+    }
+}
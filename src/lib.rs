@@ -1,13 +1,43 @@
 pub mod config;
-pub use config::{Config, Profile};
+pub use config::{Config, MirrorFallback, Monitor, Profile, Settings};
 
 pub mod manager;
 pub use manager::Manager;
 
+pub mod fallback;
+pub use fallback::{FallbackPolicy, UnlistedOutputs};
+
+pub mod conditions;
+pub use conditions::{Conditions, Machine};
+
+pub mod hooks;
+pub use hooks::Hooks;
+
+pub mod layout;
+pub use layout::{Backend, Layout};
+
+pub mod matcher;
+pub use matcher::{EdidMatch, Glob, Matcher, Selection, Similarity};
+
+pub mod placement;
+pub use placement::{Align, Placement, Relation};
+
 pub mod output;
-pub use output::Output;
+pub use output::{Edids, Output, OutputMode, OutputSettings};
+
+pub mod edid;
+pub use edid::Edid;
+
+pub mod validate;
+pub use validate::Issue;
+
+pub mod confirm;
 
 pub mod errors;
 pub use errors::{Result, Error};
 
+pub(crate) mod randr;
 pub(crate) mod xhandle;
+
+#[cfg(test)]
+pub(crate) mod testing;
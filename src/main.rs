@@ -1,21 +1,71 @@
-use clap;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
 
-use autorandr::{Config, Manager};
+use autorandr::{validate, Backend, Config, Manager};
 
 fn list(mgr: Manager, _: &clap::ArgMatches) -> Result<()> {
     mgr.list();
     Ok(())
 }
 
-fn reconcile(mgr: Manager, _: &clap::ArgMatches) -> Result<()> {
-    mgr.reconcile()?;
+fn reconcile(mgr: Manager, subm: &clap::ArgMatches) -> Result<()> {
+    mgr.with_confirm(confirm_timeout(subm))
+        .with_force(subm.get_flag("force"))
+        .reconcile()?;
     Ok(())
 }
 
-fn mirror(mgr: Manager, _: &clap::ArgMatches) -> Result<()> {
-    mgr.mirror()?;
+fn load(mgr: Manager, subm: &clap::ArgMatches) -> Result<()> {
+    let name = subm
+        .get_one::<String>("name")
+        .ok_or_else(|| anyhow!("missing profile name"))?;
+    mgr.with_confirm(confirm_timeout(subm)).load(name)?;
+    Ok(())
+}
+
+fn confirm(_: &clap::ArgMatches) -> Result<()> {
+    autorandr::confirm::confirm()?;
+    log::info!("layout confirmed");
+    Ok(())
+}
+
+fn confirm_timeout(subm: &clap::ArgMatches) -> Option<Duration> {
+    subm.get_one::<u64>("confirm").copied().map(Duration::from_secs)
+}
+
+fn confirm_arg() -> clap::Arg {
+    clap::Arg::new("confirm")
+        .long("confirm")
+        .value_name("SECONDS")
+        .help("revert to the previous layout unless confirmed within SECONDS")
+        .value_parser(clap::value_parser!(u64))
+}
+
+fn mirror(mut mgr: Manager, subm: &clap::ArgMatches) -> Result<()> {
+    let outputs = subm
+        .get_many::<String>("outputs")
+        .map(|v| v.cloned().collect::<Vec<String>>())
+        .unwrap_or_default();
+    mgr.mirror(subm.get_one::<String>("source").map(String::as_str), &outputs)?;
+    Ok(())
+}
+
+fn current(mgr: Manager, subm: &clap::ArgMatches) -> Result<()> {
+    mgr.current(subm.get_flag("name-only"))?;
+    Ok(())
+}
+
+fn save(mgr: Manager, subm: &clap::ArgMatches) -> Result<()> {
+    let name = subm
+        .get_one::<String>("name")
+        .ok_or_else(|| anyhow!("missing profile name"))?;
+    mgr.save(name, subm.get_flag("force"))?;
+    Ok(())
+}
+
+fn daemon(mgr: Manager, _: &clap::ArgMatches) -> Result<()> {
+    mgr.daemon()?;
     Ok(())
 }
 
@@ -24,20 +74,100 @@ fn profiles(mgr: Manager, _: &clap::ArgMatches) -> Result<()> {
     Ok(())
 }
 
+fn validate(backend: Backend) -> Result<()> {
+    let issues = validate::validate(&Config::dir()?, backend)?;
+    for issue in &issues {
+        log::info!("{}", issue);
+    }
+    if issues.is_empty() {
+        log::info!("no problems found");
+        Ok(())
+    } else {
+        Err(anyhow!("found {} problem(s)", issues.len()))
+    }
+}
+
 fn cli() -> Result<clap::Command> {
     Ok(clap::Command::new("autorandr")
         .author("wayne warren")
         .version("0.0.1")
         .about("xrandr, automatically")
-        .args([clap::Arg::new("verbose")
-            .short('v')
-            .help("verbosity")
-            .action(clap::ArgAction::Count)])
+        .args([
+            clap::Arg::new("verbose")
+                .short('v')
+                .help("verbosity")
+                .action(clap::ArgAction::Count),
+            clap::Arg::new("backend")
+                .long("backend")
+                .help("how to apply layouts: RandR requests over the X connection, or the xrandr binary")
+                .value_parser(["native", "xrandr"])
+                .default_value("native")
+                .global(true),
+            clap::Arg::new("dry-run")
+                .long("dry-run")
+                .short('n')
+                .help("select a layout and show it without applying it")
+                .action(clap::ArgAction::SetTrue)
+                .global(true),
+        ])
         .subcommands(vec![
             clap::Command::new("list").about("list active, connected, and disconnected outputs"),
-            clap::Command::new("reconcile").about("automatically choose from available profiles based on avaliable monitors"),
-            clap::Command::new("mirror").about("automatically current main display onto available monitor"),
+            clap::Command::new("reconcile")
+                .about("automatically choose from available profiles based on avaliable monitors")
+                .args([
+                    confirm_arg(),
+                    clap::Arg::new("force")
+                        .long("force")
+                        .short('f')
+                        .help("apply the selected profile even if it is already in effect")
+                        .action(clap::ArgAction::SetTrue),
+                ]),
+            clap::Command::new("load")
+                .about("apply the named profile")
+                .args([
+                    clap::Arg::new("name")
+                        .help("name of the profile to apply")
+                        .required(true),
+                    confirm_arg(),
+                ]),
+            clap::Command::new("confirm").about("keep a layout applied with --confirm"),
+            clap::Command::new("mirror")
+                .about("mirror one output onto the others")
+                .args([
+                    clap::Arg::new("source")
+                        .long("source")
+                        .short('s')
+                        .help("output name or monitor alias to mirror (default: the primary output)"),
+                    clap::Arg::new("outputs")
+                        .long("outputs")
+                        .short('o')
+                        .help("comma-separated outputs or monitor aliases to mirror onto (default: all connected outputs)")
+                        .value_delimiter(','),
+                ]),
             clap::Command::new("profiles").about("show available profiles"),
+            clap::Command::new("current")
+                .about("show which profile the current layout matches")
+                .arg(
+                    clap::Arg::new("name-only")
+                        .long("name-only")
+                        .short('q')
+                        .help("print only the name of the matching profile; fail if the layout is unsaved")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+            clap::Command::new("validate").about("check profiles and monitors for problems without touching the display"),
+            clap::Command::new("daemon").about("reconcile automatically whenever outputs are connected or disconnected"),
+            clap::Command::new("save")
+                .about("save the current layout of active outputs as a new profile")
+                .args([
+                    clap::Arg::new("name")
+                        .help("name of the profile to create")
+                        .required(true),
+                    clap::Arg::new("force")
+                        .long("force")
+                        .short('f')
+                        .help("overwrite an existing profile with the same name")
+                        .action(clap::ArgAction::SetTrue),
+                ]),
         ]))
 }
 
@@ -64,8 +194,28 @@ fn main() -> Result<()> {
     logger_builder.try_init()?;
     log::debug!("verbosity set to {0}", level);
 
+    let backend = matches
+        .get_one::<String>("backend")
+        .map(|s| s.parse::<Backend>())
+        .transpose()?
+        .unwrap_or_default();
+
+    // these work without a display
+    match matches.subcommand() {
+        Some(("validate", _)) => {
+            return validate(backend);
+        }
+        Some(("confirm", subm)) => {
+            return confirm(subm);
+        }
+        _ => {}
+    }
+
     let cfg = Config::load()?;
-    let mgr = Manager::from(cfg)?.detect()?;
+    let mgr = Manager::from(cfg)?
+        .with_backend(backend)
+        .with_dry_run(matches.get_flag("dry-run"))
+        .detect()?;
 
     match matches.subcommand() {
         Some(("list", subm)) => {
@@ -74,12 +224,24 @@ fn main() -> Result<()> {
         Some(("profiles", subm)) => {
             profiles(mgr, subm)
         }
+        Some(("current", subm)) => {
+            current(mgr, subm)
+        }
         Some(("reconcile", subm)) => {
             reconcile(mgr, subm)
         }
+        Some(("load", subm)) => {
+            load(mgr, subm)
+        }
         Some(("mirror", subm)) => {
             mirror(mgr, subm)
         }
+        Some(("daemon", subm)) => {
+            daemon(mgr, subm)
+        }
+        Some(("save", subm)) => {
+            save(mgr, subm)
+        }
         Some((c, _)) => {
             println!("{}", cmd.render_usage());
             println!("{}", cmd.render_long_help());
@@ -1,14 +1,27 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::str;
+use std::time::Duration;
 
-use subprocess::{Exec, ExitStatus, Redirection};
+use subprocess::ExitStatus;
 
+use crate::confirm;
+use crate::fallback;
+use crate::hooks::{self, Stage};
+use crate::output::{Position, Reflect};
+use crate::matcher::{self, Similarity};
+use crate::placement;
+use crate::randr;
 use crate::xhandle::XHandleWrapper;
-use crate::{Config, Error, Output, Result};
+use crate::{Backend, Config, Error, Glob, Layout, Machine, Matcher, Output, Profile, Result};
 
 pub struct Manager {
     config: Config,
     xhandle: XHandleWrapper,
+    backend: Backend,
+    dry_run: bool,
+    confirm: Option<Duration>,
+    force: bool,
+    machine: Machine,
 
     active: HashMap<String, Output>,
     connected: HashMap<String, Output>,
@@ -20,35 +33,71 @@ impl Manager {
         Ok(Manager {
             config,
             xhandle: XHandleWrapper::open()?,
+            backend: Backend::default(),
+            dry_run: false,
+            confirm: None,
+            force: false,
+            machine: Machine::default(),
             active: HashMap::new(),
             connected: HashMap::new(),
             disconnected: Vec::new(),
         })
     }
 
+    /// Select how layouts are applied.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// When set, `reconcile`, `load`, and `mirror` report what they would do instead of doing it.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// When set, layouts are reverted unless confirmed within the given time.
+    pub fn with_confirm(mut self, timeout: Option<Duration>) -> Self {
+        self.confirm = timeout;
+        self
+    }
+
+    /// When set, `reconcile` applies the selected profile even if it is already in effect.
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Read the machine state that profile conditions check from `machine`.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
     pub fn detect(mut self) -> Result<Self> {
         self.active = HashMap::new();
         self.connected = HashMap::new();
         self.disconnected = Vec::new();
 
+        // keyed by connector name, since identical monitors may share an EDID and some monitors
+        // have none at all
         for o in self.xhandle.active_outputs()? {
-            if let Some(edid) = &o.edid {
-                self.active.insert(edid.to_string(), o);
+            if let (Some(name), true) = (&o.output_name, o.connected) {
+                self.active.insert(name.clone(), o);
             }
         }
 
         for o in self.xhandle.inactive_outputs()? {
-            match (&o.output_name, &o.edid) {
-                (Some(_), Some(edid)) => {
-                    if self.active.contains_key(edid) {
-                        continue;
+            match &o.output_name {
+                Some(name) if self.active.contains_key(name) => continue,
+                Some(name) if o.connected => {
+                    if o.edid.is_none() {
+                        log::debug!("{} is connected but has no EDID", name);
                     }
-                    self.connected.insert(edid.to_string(), o);
-                }
-                (Some(_), None) => {
-                    self.disconnected.push(o);
+                    self.connected.insert(name.clone(), o);
                 }
-                (_, _) => continue,
+                Some(_) => self.disconnected.push(o),
+                None => continue,
             }
         }
 
@@ -56,24 +105,54 @@ impl Manager {
     }
 
     pub fn list(&self) {
-        if self.active.len() > 0 {
+        if !self.active.is_empty() {
             log::info!("connected (active):");
-            for (_, output) in &self.active {
+            for output in self.active.values() {
                 log::info!(" name: {0}", output.output_name.as_ref().unwrap());
-                log::info!(" edid: {0}", output.edid.as_ref().unwrap());
+                if let Some(monitor) = self.config.monitor_for(output) {
+                    log::info!(" alias: {0}", monitor.name());
+                }
+                match &output.edid {
+                    Some(edid) => log::info!(" edid: {0}", edid),
+                    None => log::info!(" edid: none"),
+                }
+                if let Some(info) = &output.info {
+                    log::info!(" monitor: {0}", info);
+                }
+                if let Some(path) = &output.path {
+                    log::info!(" path: {0}", path);
+                }
+                if let Some(id) = &output.connector_id {
+                    log::info!(" connector id: {0}", id);
+                }
             }
         }
 
-        if self.connected.len() > 0 {
+        if !self.connected.is_empty() {
             log::info!("");
             log::info!("connected (inactive):");
-            for (_, output) in &self.connected {
+            for output in self.connected.values() {
                 log::info!(" name: {}", output.output_name.as_ref().unwrap());
-                log::info!(" edid: {0}", output.edid.as_ref().unwrap());
+                if let Some(monitor) = self.config.monitor_for(output) {
+                    log::info!(" alias: {0}", monitor.name());
+                }
+                match &output.edid {
+                    Some(edid) => log::info!(" edid: {0}", edid),
+                    None => log::info!(" edid: none"),
+                }
+                if let Some(info) = &output.info {
+                    log::info!(" monitor: {0}", info);
+                }
+                if let Some(path) = &output.path {
+                    log::info!(" path: {0}", path);
+                }
+                if let Some(id) = &output.connector_id {
+                    log::info!(" connector id: {0}", id);
+                }
             }
         }
 
-        if self.disconnected.len() > 0 {
+        if !self.disconnected.is_empty() {
             log::info!("");
             log::info!("disconnected:");
             for output in &self.disconnected {
@@ -89,137 +168,679 @@ impl Manager {
         }
     }
 
-    pub fn reconcile(&self) -> Result<()> {
-        let mut cmd = Exec::cmd("xrandr").stderr(Redirection::Merge);
-        for output in &self.disconnected {
-            match &output.output_name {
-                Some(name) => {
-                    cmd = cmd.arg("--output").arg(&name).arg("--off");
+    /// Report which profile the live layout matches exactly, or else the closest one. With
+    /// `name_only`, print just the name of an exactly matching profile, failing if there is none.
+    pub fn current(&self, name_only: bool) -> Result<()> {
+        let detected = self.active.values().chain(self.connected.values());
+        let matcher = Matcher::new(detected);
+        let active = self.active.values().collect::<Vec<&Output>>();
+        let inactive = self.connected.values().collect::<Vec<&Output>>();
+
+        let mut best: Option<(&Profile, Similarity)> = None;
+        for profile in &self.config.profiles {
+            let assignment = match matcher.assign(profile) {
+                Some(assignment) if !assignment.is_empty() => assignment,
+                _ => continue,
+            };
+            let positions = placement::positions(profile, &assignment).unwrap_or_default();
+            let similarity = matcher::compare(profile, &assignment, &positions, &active, &inactive);
+            // prefer exact matches, then the largest share of matching settings
+            let better = match &best {
+                None => true,
+                Some((_, b)) => {
+                    (similarity.is_exact(), similarity.matched * b.checked)
+                        > (b.is_exact(), b.matched * similarity.checked)
                 }
-                None => (),
             };
+            if better {
+                best = Some((profile, similarity));
+            }
+        }
+
+        match best {
+            Some((profile, similarity)) if similarity.is_exact() => {
+                if name_only {
+                    println!("{}", profile.name());
+                } else {
+                    log::info!("current layout matches profile '{}'", profile.name());
+                }
+            }
+            _ if name_only => return Err(Error::UnsavedLayout),
+            Some((profile, similarity)) => {
+                log::info!(
+                    "unsaved layout; closest profile is '{}' ({} of {} settings match):",
+                    profile.name(),
+                    similarity.matched,
+                    similarity.checked
+                );
+                for difference in &similarity.differences {
+                    log::info!(" {}", difference);
+                }
+            }
+            None => log::info!("unsaved layout"),
         }
+        Ok(())
+    }
 
-        let mut available: HashSet<String> = HashSet::new();
-        for (edid, _) in &self.active {
-            available.insert(edid.clone());
+    /// Save the current layout of active outputs as a new profile named `name`.
+    pub fn save(&self, name: &str, force: bool) -> Result<()> {
+        if self.active.is_empty() {
+            return Err(Error::NoActiveMonitors);
         }
 
-        for (edid, _) in &self.connected {
-            available.insert(edid.clone());
+        let mut outputs = self
+            .active
+            .values()
+            .filter_map(|o| o.output_name.clone().map(|name| (name, o.clone())))
+            .collect::<BTreeMap<String, Output>>();
+        // pin monitors that share an EDID to their connectors so they can be told apart...
+        let edids = outputs.values().filter_map(|o| o.edid_str()).collect::<Vec<&str>>();
+        let shared = outputs
+            .iter()
+            .filter(|(_, o)| o.edid_str().is_some_and(|e| edids.iter().filter(|x| **x == e).count() > 1))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<String>>();
+        for name in shared {
+            outputs.get_mut(&name).unwrap().connector = Some(Glob::new(&name));
+        }
+        // and those without one, which nothing else identifies
+        for (name, output) in outputs.iter_mut().filter(|(_, o)| o.edid.is_none()) {
+            output.connector = Some(Glob::new(name));
         }
 
-        for profile in &self.config.profiles {
-            if profile.is_available(&available) {
-                for (_, profile_output) in &profile.outputs {
-                    let output = match (
-                        self.active.get(profile_output.edid.as_ref().unwrap()),
-                        self.connected.get(profile_output.edid.as_ref().unwrap()),
-                    ) {
-                        (Some(m), None) => m,
-                        (None, Some(m)) => m,
-                        (Some(_), Some(_)) => {
-                            // logically this should be unreachable given how Manager.detect()
-                            // populates Manager.active and Manager.connected
-                            unreachable!("meow");
-                        }
-                        (None, None) => {
-                            // we shouldn't be able to reach this point since the
-                            // profile_output.edid is confirmed to be among the `available` edids
-                            // in the profile.is_available() method
-                            unreachable!("meow");
-                        }
-                    };
-                    cmd = cmd
-                        .arg("--output")
-                        .arg(output.output_name.as_ref().unwrap());
-                    log::debug!("{:?}", profile_output.get_args());
-                    cmd = cmd.args(&profile_output.get_args());
-                }
-                break;
-            }
-        }
-
-        let cmdline = cmd.to_cmdline_lossy();
-        let capture_data = cmd.capture()?;
-        match capture_data.exit_status {
-            ExitStatus::Exited(0) => {
-                log::info!("'{}' succeeded", cmdline);
-                Ok(())
-            }
-            ExitStatus::Exited(s) => {
-                log::debug!("{}", str::from_utf8(&capture_data.stderr)?);
-                Err(Error::SubprocessFailed(cmdline, s))
-            }
-            ExitStatus::Signaled(s) => Err(Error::SubprocessKilledBySignal(cmdline, s)),
-            _ => Err(Error::SubprocessUnknownFailure(cmdline)),
-        }
-    }
-
-    pub fn mirror(&self) -> Result<()> {
-        let mut cmd = Exec::cmd("xrandr").stderr(Redirection::Merge);
+        let profile = Profile::new(name, outputs);
+        let path = Config::save_profile(&profile, force)?;
+        log::info!("saved profile '{}' to {}", name, path.to_string_lossy());
+        Ok(())
+    }
+
+    pub fn reconcile(&mut self) -> Result<()> {
+        let mut layout = Layout::default();
         for output in &self.disconnected {
-            match &output.output_name {
-                Some(name) => {
-                    cmd = cmd.arg("--output").arg(&name).arg("--off");
-                }
-                None => (),
-            };
+            if let Some(name) = &output.output_name {
+                layout.off(name);
+            }
         }
 
-        let mirror_profile_output = self
+        let eligible = self
             .config
             .profiles
             .iter()
-            .find_map(|p| {
-                if p.name() == "mirror" {
-                    Some(p.outputs.get("all-monitors").unwrap())
-                } else {
-                    None
+            .filter(|p| {
+                let holds = p.conditions_hold(&self.machine);
+                if !holds {
+                    log::debug!("skipping profile '{}': its conditions don't hold", p.name());
                 }
+                holds
             })
+            .cloned()
+            .collect::<Vec<Profile>>();
+        let matcher = Matcher::new(self.active.values().chain(self.connected.values()));
+        if let Some(selection) = matcher.select(&eligible) {
+            log::info!("selected profile '{}'", selection.profile.name());
+            if !self.force && self.is_applied(selection.profile, &selection.assignment)? {
+                log::info!("profile '{}' is already applied; nothing to do", selection.profile.name());
+                return Ok(());
+            }
+            let detected = self.active.values().chain(self.connected.values()).collect::<Vec<&Output>>();
+            add_profile(&mut layout, selection.profile, &selection.assignment, &detected)?;
+        } else {
+            let policy = self.config.settings.fallback;
+            if policy == fallback::FallbackPolicy::None {
+                log::info!("no profile matched; leaving outputs as they are");
+                return Ok(());
+            }
+            log::info!("no profile matched; falling back to '{}'", policy);
+            let outputs = self
+                .active
+                .values()
+                .chain(self.connected.values())
+                .collect::<Vec<&Output>>();
+            policy.arrange(&outputs, &mut layout)?;
+        }
+
+        self.apply(&layout)
+    }
+
+    /// Apply the named profile, provided that the connected monitors can fill it.
+    pub fn load(&mut self, name: &str) -> Result<()> {
+        let mut layout = Layout::default();
+        for output in &self.disconnected {
+            if let Some(name) = &output.output_name {
+                layout.off(name);
+            }
+        }
+
+        let profile = self
+            .config
+            .profile(name)
+            .ok_or_else(|| Error::UnknownProfile(name.into()))?;
+        let detected = self.active.values().chain(self.connected.values()).collect::<Vec<&Output>>();
+        let assignment = Matcher::new(detected.iter().copied())
+            .assign(profile)
+            .ok_or_else(|| Error::ProfileDoesNotMatch(name.into()))?;
+        add_profile(&mut layout, profile, &assignment, &detected)?;
+
+        self.apply(&layout)
+    }
+
+    /// Mirror `source` (an output name or monitor alias; by default the primary output) onto
+    /// `outputs`, or onto every other connected output when `outputs` is empty. Active outputs
+    /// that don't join the mirror are turned off.
+    pub fn mirror(&mut self, source: Option<&str>, outputs: &[String]) -> Result<()> {
+        let layout = self.mirror_layout(source, outputs)?;
+        self.apply(&layout)
+    }
+
+    fn mirror_layout(&self, source: Option<&str>, outputs: &[String]) -> Result<Layout> {
+        let mut layout = Layout::default();
+        for output in &self.disconnected {
+            if let Some(name) = &output.output_name {
+                layout.off(name);
+            }
+        }
+
+        let mirror_profile = self
+            .config
+            .profiles
+            .iter()
+            .find(|p| p.name() == "mirror")
+            .ok_or(Error::MirrorModeMissingProfile)?;
+        let mirror_profile_output = mirror_profile
+            .outputs
+            .get("all-monitors")
             .ok_or(Error::MirrorModeMissingProfile)?;
 
-        let mut actives = self.active.iter();
-        let active = if let Some((_edid, output)) = actives.next() {
-            output
+        layout.profile = Some("mirror".into());
+
+        // the primary output first, then other active outputs, then connected ones
+        let mut actives = self.active.values().collect::<Vec<&Output>>();
+        actives.sort_by_key(|o| (!o.settings.primary, o.output_name.clone()));
+        let mut connected = self.connected.values().collect::<Vec<&Output>>();
+        connected.sort_by_key(|o| o.output_name.clone());
+        let candidates = actives.into_iter().chain(connected).collect::<Vec<&Output>>();
+
+        let source = match source {
+            Some(name) => self.find_output(&candidates, name)?,
+            None => *candidates.first().ok_or(Error::NoActiveMonitors)?,
+        };
+        let mut mirrored = vec![source];
+        if outputs.is_empty() {
+            mirrored.extend(candidates.iter().filter(|o| o.output_name != source.output_name));
         } else {
-            return Err(Error::NoActiveMonitors);
+            for name in outputs {
+                let output = self.find_output(&candidates, name)?;
+                if !mirrored.iter().any(|o| o.output_name == output.output_name) {
+                    mirrored.push(output);
+                }
+            }
+        }
+        for output in self.active.values() {
+            if !mirrored.iter().any(|o| o.output_name == output.output_name) {
+                layout.off(output.output_name.as_ref().unwrap());
+            }
+        }
+
+        // mode and position are chosen below so that every output shows the same area
+        let mut base = mirror_profile_output.clone();
+        base.settings.mode = None;
+        base.settings.pos = None;
+        base.settings.scale_from = None;
+        let base_args = base.get_args();
+        log::debug!("{:?}", base_args);
+
+        fallback::mirror(&mirrored, &base_args, mirror_profile.mirror_fallback(), &mut layout)?;
+        Ok(layout)
+    }
+
+    /// The output among `candidates` with the given output name or monitor alias.
+    fn find_output<'a>(&self, candidates: &[&'a Output], name: &str) -> Result<&'a Output> {
+        candidates
+            .iter()
+            .copied()
+            .find(|o| o.output_name.as_deref() == Some(name))
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .copied()
+                    .find(|o| self.config.monitor_for(o).is_some_and(|m| m.name() == name))
+            })
+            .ok_or_else(|| Error::UnknownOutput(name.into()))
+    }
+
+    /// Reconcile now and again every time outputs are connected or disconnected, reusing this
+    /// `Manager`'s configuration and X connection for the lifetime of the process. Lid and power
+    /// changes that leave the outputs alone don't trigger a reconcile.
+    pub fn daemon(mut self) -> Result<()> {
+        log::info!("watching for output changes");
+        // select notifications first so that changes made while reconciling aren't missed
+        self.xhandle.watch_changes()?;
+        loop {
+            let reconciled = self.monitors();
+            if let Err(e) = self.reconcile() {
+                log::warn!("reconcile failed: {}", e);
+            }
+            let changed;
+            (self, changed) = self.redetect(&reconciled)?;
+            if changed {
+                log::info!("outputs changed while reconciling");
+                continue;
+            }
+
+            self.xhandle.wait_for_change()?;
+            log::info!("output change detected");
+            self = self.detect()?;
+        }
+    }
+
+    /// The connected outputs and the EDIDs of their monitors.
+    fn monitors(&self) -> BTreeMap<String, Option<String>> {
+        self.active
+            .iter()
+            .chain(self.connected.iter())
+            .map(|(name, o)| (name.clone(), o.edid_str().map(String::from)))
+            .collect()
+    }
+
+    /// Drop the notifications caused by applying a layout, then detect outputs again, since a
+    /// monitor may have been plugged in or unplugged meanwhile. Reports whether the monitors
+    /// differ from those `reconciled`.
+    fn redetect(mut self, reconciled: &BTreeMap<String, Option<String>>) -> Result<(Self, bool)> {
+        self.xhandle.discard_changes();
+        let manager = self.detect()?;
+        let changed = manager.monitors() != *reconciled;
+        Ok((manager, changed))
+    }
+
+    fn apply(&mut self, layout: &Layout) -> Result<()> {
+        if self.dry_run {
+            log::info!("dry run, not applying:");
+            log::info!(" profile: {}", layout.profile.as_deref().unwrap_or("(none)"));
+            for output in &layout.outputs {
+                log::info!(" {}: {}", output.name, output.args.join(" "));
+            }
+            log::info!(" command: {}", layout.xrandr_command().to_cmdline_lossy());
+            return Ok(());
+        }
+
+        let timeout = match self.confirm {
+            Some(timeout) => timeout,
+            None => return self.switch(layout),
         };
+        let previous = self.current_layout()?;
+        let pending = confirm::Pending::create()?;
+        if let Err(e) = self.switch(layout) {
+            log::warn!("failed to apply layout; reverting to the previous one");
+            if let Err(revert) = self.switch(&previous) {
+                log::warn!("failed to revert: {}", revert);
+            }
+            return Err(e);
+        }
+        if pending.wait(timeout) {
+            log::info!("layout confirmed");
+            return Ok(());
+        }
+        log::warn!("layout not confirmed; reverting to the previous one");
+        self.switch(&previous)?;
+        Err(Error::NotConfirmed(timeout.as_secs()))
+    }
 
-        cmd = cmd
-            .arg("--output")
-            .arg(active.output_name.as_ref().unwrap());
-        log::debug!("{:?}", mirror_profile_output.get_args());
-        cmd = cmd.args(&mirror_profile_output.get_args());
+    /// Whether the live layout already is what applying `profile` would produce. Settings that
+    /// can't be read back from the server (scaling, transforms, reflection, and raw
+    /// `xrandr_args`) count as not applied.
+    fn is_applied(&self, profile: &Profile, assignment: &BTreeMap<String, &Output>) -> Result<bool> {
+        let unverifiable = assignment.keys().map(|slot| &profile.outputs[slot]).any(|o| {
+            let s = &o.settings;
+            s.scale.is_some()
+                || s.scale_from.is_some()
+                || s.transform.is_some()
+                || s.reflect.is_some_and(|r| r != Reflect::Normal)
+                || s.auto
+                || o.xrandr_args.as_ref().is_some_and(|a| !a.is_empty())
+        });
+        if unverifiable || self.disconnected.iter().any(|o| o.settings.mode.is_some()) {
+            return Ok(false);
+        }
 
-        if actives.next().is_some() {
-            return Err(Error::MirrorModeTooManyActiveMonitors);
+        let positions = placement::positions(profile, assignment)?;
+        let active = self.active.values().collect::<Vec<&Output>>();
+        let inactive = self.connected.values().collect::<Vec<&Output>>();
+        let similarity = matcher::compare(profile, assignment, &positions, &active, &inactive);
+        for difference in &similarity.differences {
+            log::debug!("{}", difference);
         }
+        Ok(similarity.is_exact())
+    }
 
-        for (_edid, output) in &self.connected {
-            // TODO: fail if all available monitors do not have the same resolution as the current active
-            // monitor
-            cmd = cmd
-                .arg("--output")
-                .arg(output.output_name.as_ref().unwrap());
-            log::debug!("{:?}", mirror_profile_output.get_args());
-            cmd = cmd.args(&mirror_profile_output.get_args());
+    /// The layout the outputs are in now, for reverting to.
+    fn current_layout(&mut self) -> Result<Layout> {
+        let mut layout = Layout::default();
+        for output in self.active.values() {
+            let name = output.output_name.as_ref().unwrap();
+            let mut settings = output.settings.clone();
+            (settings.reflect, settings.transform) = self.xhandle.reflection_and_transform(name)?;
+            layout.set(name, settings.to_args());
         }
+        for output in self.connected.values() {
+            layout.off(output.output_name.as_ref().unwrap());
+        }
+        Ok(layout)
+    }
 
-        let cmdline = cmd.to_cmdline_lossy();
-        let capture_data = cmd.capture()?;
-        match capture_data.exit_status {
-            ExitStatus::Exited(0) => {
-                log::info!("'{}' succeeded", cmdline);
-                Ok(())
+    /// Run the hooks around applying `layout` with the configured backend.
+    fn switch(&mut self, layout: &Layout) -> Result<()> {
+        // fail before any hooks run
+        if self.backend == Backend::Native {
+            if let Some((output, arg)) = randr::unsupported_argument(layout) {
+                return Err(Error::UnsupportedArgument(output.into(), arg.into()));
             }
-            ExitStatus::Exited(s) => {
-                log::debug!("{}", str::from_utf8(&capture_data.stderr)?);
-                Err(Error::SubprocessFailed(cmdline, s))
+        }
+        let global_dir = Config::dir()?;
+        let profile_hooks = layout
+            .profile
+            .as_ref()
+            .and_then(|name| self.config.profile(name))
+            .map(|p| &p.hooks);
+        let env = hooks::env(
+            layout.profile.as_deref(),
+            self.active
+                .values()
+                .chain(self.connected.values())
+                .filter_map(|o| Some((o.output_name.as_deref()?, o.edid_str()?))),
+        );
+
+        hooks::run(Stage::Preswitch, &global_dir, profile_hooks, &env)?;
+        match self.backend {
+            Backend::Native => {
+                self.xhandle.apply(layout)?;
+                log::info!("applied '{}'", layout.xrandr_command().to_cmdline_lossy());
             }
-            ExitStatus::Signaled(s) => Err(Error::SubprocessKilledBySignal(cmdline, s)),
-            _ => Err(Error::SubprocessUnknownFailure(cmdline)),
+            Backend::Xrandr => run_xrandr(layout)?,
+        }
+        hooks::run(Stage::Postswitch, &global_dir, profile_hooks, &env)
+    }
+}
+
+/// Add the outputs of `profile` to `layout`, configured as `assignment` says, along with those of
+/// the `detected` outputs that the profile leaves out, as its `unlisted_outputs` policy says.
+fn add_profile(
+    layout: &mut Layout,
+    profile: &Profile,
+    assignment: &BTreeMap<String, &Output>,
+    detected: &[&Output],
+) -> Result<()> {
+    layout.profile = Some(profile.name().into());
+    let positions = placement::positions(profile, assignment)?;
+    for (slot, output) in assignment {
+        let mut profile_output = profile.outputs[slot].clone();
+        if let Some((x, y)) = positions.get(slot) {
+            profile_output.settings.pos = Some(Position { x: *x, y: *y });
+        }
+        // without an EDID there may be no preferred mode, and the server would pick the first one
+        let settings = &mut profile_output.settings;
+        let lit = output.settings.mode.is_some();
+        if output.edid.is_none() && !lit && settings.mode.is_none() && !settings.off && !settings.auto {
+            settings.mode = output.preferred_mode().map(|m| m.name.clone());
+        }
+        log::debug!("{:?}", profile_output.get_args());
+        layout.set(output.output_name.as_ref().unwrap(), profile_output.get_args());
+    }
+
+    let unlisted = detected
+        .iter()
+        .filter(|d| !assignment.values().any(|a| a.output_name == d.output_name))
+        .copied()
+        .collect::<Vec<&Output>>();
+    if !unlisted.is_empty() {
+        let policy = profile.unlisted_outputs();
+        log::debug!("unlisted outputs: {:?}", unlisted.iter().map(|o| &o.output_name).collect::<Vec<_>>());
+        let areas = placement::areas(profile, assignment, &positions);
+        let primary = assignment
+            .keys()
+            .find(|slot| profile.outputs[*slot].settings.primary)
+            .map(|slot| slot.as_str());
+        policy.arrange(&unlisted, &areas, primary, layout);
+    }
+    Ok(())
+}
+
+fn run_xrandr(layout: &Layout) -> Result<()> {
+    let cmd = layout.xrandr_command();
+    let cmdline = cmd.to_cmdline_lossy();
+    let capture_data = cmd.capture()?;
+    match capture_data.exit_status {
+        ExitStatus::Exited(0) => {
+            log::info!("'{}' succeeded", cmdline);
+            Ok(())
         }
+        ExitStatus::Exited(s) => {
+            log::debug!("{}", str::from_utf8(&capture_data.stderr)?);
+            Err(Error::SubprocessFailed(cmdline, s))
+        }
+        ExitStatus::Signaled(s) => Err(Error::SubprocessKilledBySignal(cmdline, s)),
+        _ => Err(Error::SubprocessUnknownFailure(cmdline)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{FallbackPolicy, MirrorFallback, OutputMode};
+
+    /// A connected output supporting a single mode, showing it at `pos` when given.
+    fn output(name: &str, width: u32, height: u32, pos: Option<&str>) -> Output {
+        let mode = format!("{}x{}", width, height);
+        let settings = match pos {
+            Some(pos) => format!("{{mode: {}, pos: {}}}", mode, pos),
+            None => "{}".into(),
+        };
+        Output {
+            output_name: Some(name.into()),
+            connected: true,
+            modes: vec![OutputMode {
+                name: mode,
+                width,
+                height,
+                refresh: 60000,
+                preferred: true,
+            }],
+            settings: serde_yaml::from_str(&settings).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    /// A manager with a single active laptop panel, "eDP-1", showing 1920x1080 at the origin.
+    fn manager(profile: Profile, xhandle: XHandleWrapper) -> Manager {
+        let laptop = output("eDP-1", 1920, 1080, Some("0x0"));
+        Manager {
+            config: Config {
+                settings: Default::default(),
+                profiles: vec![profile],
+                monitors: Vec::new(),
+            },
+            xhandle,
+            backend: Backend::Native,
+            dry_run: false,
+            confirm: None,
+            force: false,
+            machine: Machine::default(),
+            active: HashMap::from([("eDP-1".to_string(), laptop)]),
+            connected: HashMap::new(),
+            disconnected: Vec::new(),
+        }
+    }
+
+    /// A profile with a single output, `slot`, described by `output`.
+    fn profile(name: &str, slot: &str, output: &str) -> Profile {
+        Profile::new(name, BTreeMap::from([(slot.into(), serde_yaml::from_str(output).unwrap())]))
+    }
+
+    fn laptop(settings: &str) -> Profile {
+        profile("laptop", "panel", &format!("{{connector: eDP-1, settings: {}}}", settings))
+    }
+
+    #[test]
+    fn applied_only_when_live_layout_matches() {
+        let is_applied = |settings: &str| {
+            let mgr = manager(laptop(settings), XHandleWrapper::faux());
+            let profile = &mgr.config.profiles[0];
+            let assignment = Matcher::new(mgr.active.values()).assign(profile).unwrap();
+            mgr.is_applied(profile, &assignment).unwrap()
+        };
+
+        assert!(is_applied("{mode: 1920x1080, pos: 0x0}"));
+        assert!(!is_applied("{mode: 1920x1080, pos: 0x0, rotate: left}"));
+        assert!(!is_applied("{mode: 1280x720, pos: 0x0}"));
+        // scaling can't be read back, so it is always reapplied
+        assert!(!is_applied("{mode: 1920x1080, pos: 0x0, scale: 2}"));
+    }
+
+    #[test]
+    fn reconcile_skips_applied_profile_unless_forced() {
+        let settings = "{mode: 1920x1080, pos: 0x0}";
+
+        // applying would panic, as `apply` isn't stubbed
+        let mut mgr = manager(laptop(settings), XHandleWrapper::faux());
+        mgr.reconcile().unwrap();
+
+        let applied = Arc::new(AtomicUsize::new(0));
+        let mut xhandle = XHandleWrapper::faux();
+        let counter = applied.clone();
+        faux::when!(xhandle.apply).then(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        let mut mgr = manager(laptop(settings), xhandle).with_force(true);
+        mgr.reconcile().unwrap();
+        assert_eq!(applied.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn reconcile_does_nothing_without_match_under_none_policy() {
+        // applying would panic, as `apply` isn't stubbed
+        let mut mgr = manager(profile("desk", "main", "{connector: DP-1}"), XHandleWrapper::faux());
+        mgr.config.settings.fallback = FallbackPolicy::None;
+        mgr.reconcile().unwrap();
+    }
+
+    #[test]
+    fn redetect_notices_monitors_plugged_in_while_reconciling() {
+        let mut xhandle = XHandleWrapper::faux();
+        faux::when!(xhandle.discard_changes).then_return(());
+        faux::when!(xhandle.active_outputs)
+            .then(|_| Ok(vec![output("eDP-1", 1920, 1080, Some("0x0"))]));
+        faux::when!(xhandle.inactive_outputs)
+            .then(|_| Ok(vec![output("DP-1", 2560, 1440, None)]));
+        let mgr = manager(laptop("{}"), xhandle);
+
+        let reconciled = mgr.monitors();
+        let (mgr, changed) = mgr.redetect(&reconciled).unwrap();
+        assert!(changed);
+        assert!(mgr.connected.contains_key("DP-1"));
+
+        let reconciled = mgr.monitors();
+        let (_, changed) = mgr.redetect(&reconciled).unwrap();
+        assert!(!changed);
+    }
+
+    #[test]
+    fn native_backend_rejects_unsupported_arguments() {
+        // applying would panic, as `apply` isn't stubbed
+        let mut mgr = manager(
+            profile("laptop", "panel", "{connector: eDP-1, xrandr_args: {--brightness: \"0.8\"}}"),
+            XHandleWrapper::faux(),
+        );
+        assert!(matches!(
+            mgr.reconcile(),
+            Err(Error::UnsupportedArgument(output, arg))
+                if output == "eDP-1" && arg == "--brightness"
+        ));
+    }
+
+    #[test]
+    fn add_profile_positioned_by_xrandr_args() {
+        let laptop = output("eDP-1", 1920, 1080, Some("0x0"));
+        let desk = output("DP-1", 2560, 1440, None);
+        let tv = output("HDMI-1", 1920, 1080, None);
+        let profile = Profile::new(
+            "legacy",
+            BTreeMap::from([
+                ("laptop".into(), serde_yaml::from_str("{settings: {pos: 0x0}}").unwrap()),
+                (
+                    "desk".into(),
+                    serde_yaml::from_str("{xrandr_args: {--right-of: eDP-1}}").unwrap(),
+                ),
+                ("tv".into(), serde_yaml::from_str("{settings: {auto: true}}").unwrap()),
+            ]),
+        );
+        let assignment = BTreeMap::from([
+            ("laptop".to_string(), &laptop),
+            ("desk".to_string(), &desk),
+            ("tv".to_string(), &tv),
+        ]);
+
+        // "desk" and "tv" have no `pos` and would overlap "laptop" at the origin
+        let mut layout = Layout::default();
+        add_profile(&mut layout, &profile, &assignment, &[&laptop, &desk, &tv]).unwrap();
+        let args = |name: &str| {
+            let output = layout.outputs.iter().find(|o| o.name == name).unwrap();
+            output.args.join(" ")
+        };
+        assert_eq!(args("DP-1"), "--mode 2560x1440 --right-of eDP-1");
+        assert_eq!(args("HDMI-1"), "--auto");
+    }
+
+    #[test]
+    fn mirror_source_and_outputs() {
+        let mut mirror = profile("mirror", "all-monitors", "{}");
+        mirror.mirror_fallback = Some(MirrorFallback::Scale);
+        let mut mgr = manager(mirror, XHandleWrapper::faux());
+        mgr.active.get_mut("eDP-1").unwrap().settings.primary = true;
+        mgr.active.insert("DP-1".into(), output("DP-1", 2560, 1440, Some("1920x0")));
+        mgr.connected.insert("HDMI-1".into(), output("HDMI-1", 1920, 1080, None));
+        let mirrored = |source: Option<&str>, outputs: &[&str]| {
+            let outputs = outputs.iter().map(|o| o.to_string()).collect::<Vec<String>>();
+            mgr.mirror_layout(source, &outputs).map(|layout| {
+                layout
+                    .outputs
+                    .into_iter()
+                    .map(|o| (o.name, o.args.join(" ")))
+                    .collect::<BTreeMap<String, String>>()
+            })
+        };
+
+        // the primary output by default, onto every other output
+        assert_eq!(
+            mirrored(None, &[]).unwrap(),
+            BTreeMap::from([
+                ("eDP-1".into(), "--mode 1920x1080 --pos 0x0".into()),
+                ("DP-1".into(), "--mode 2560x1440 --pos 0x0 --scale-from 1920x1080".into()),
+                ("HDMI-1".into(), "--mode 1920x1080 --pos 0x0 --scale-from 1920x1080".into()),
+            ])
+        );
+        assert_eq!(
+            mirrored(Some("DP-1"), &[]).unwrap(),
+            BTreeMap::from([
+                ("DP-1".into(), "--mode 2560x1440 --pos 0x0".into()),
+                ("eDP-1".into(), "--mode 1920x1080 --pos 0x0 --scale-from 2560x1440".into()),
+                ("HDMI-1".into(), "--mode 1920x1080 --pos 0x0 --scale-from 2560x1440".into()),
+            ])
+        );
+        // active outputs left out of the mirror are turned off
+        assert_eq!(
+            mirrored(Some("HDMI-1"), &["eDP-1"]).unwrap(),
+            BTreeMap::from([
+                ("DP-1".into(), "--off".into()),
+                ("HDMI-1".into(), "--mode 1920x1080 --pos 0x0".into()),
+                ("eDP-1".into(), "--mode 1920x1080 --pos 0x0".into()),
+            ])
+        );
+        assert!(matches!(
+            mirrored(Some("VGA-1"), &[]),
+            Err(Error::UnknownOutput(name)) if name == "VGA-1"
+        ));
     }
 }
@@ -0,0 +1,712 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::{Position, Rotate};
+use crate::{Edid, Edids, Output, Profile, UnlistedOutputs};
+
+/// A case-insensitive shell-style pattern where `*` matches any run of characters and `?` matches
+/// exactly one.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(transparent)]
+pub struct Glob(String);
+
+impl Glob {
+    pub fn new(pattern: &str) -> Glob {
+        Glob(pattern.into())
+    }
+
+    pub fn matches(&self, s: &str) -> bool {
+        let pattern = self.0.to_lowercase().chars().collect::<Vec<char>>();
+        let s = s.to_lowercase().chars().collect::<Vec<char>>();
+        glob_match(&pattern, &s)
+    }
+}
+
+fn glob_match(pattern: &[char], s: &[char]) -> bool {
+    // iterative matcher that backtracks to the most recent `*`
+    let (mut p, mut i) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((sp, si)) => {
+                    p = sp + 1;
+                    i = si + 1;
+                    star = Some((sp, si + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Conditions on the decoded EDID of a detected output. Every condition that is set must match.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(deny_unknown_fields)]
+pub struct EdidMatch {
+    /// Three-letter PNP manufacturer ID, eg "DEL".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<Glob>,
+    /// Product code as four hex digits, eg "A0F4".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<Glob>,
+    /// Serial number descriptor, or the numeric serial when there is no descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<Glob>,
+    /// Monitor name descriptor, eg "DELL U2720Q".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<Glob>,
+}
+
+impl EdidMatch {
+    pub fn matches(&self, edid: &Edid) -> bool {
+        let serial = edid
+            .serial_string
+            .clone()
+            .unwrap_or_else(|| edid.serial.to_string());
+        let checks = [
+            (&self.vendor, Some(edid.manufacturer.clone())),
+            (&self.product, Some(format!("{:04X}", edid.product_code))),
+            (&self.serial, Some(serial)),
+            (&self.name, edid.name.clone()),
+        ];
+        checks.iter().all(|(glob, value)| match (glob, value) {
+            (None, _) => true,
+            (Some(glob), Some(value)) => glob.matches(value),
+            (Some(_), None) => false,
+        })
+    }
+}
+
+/// Whether the detected output has one of `edids` or an EDID matching `matches`.
+pub fn identifies(edids: Option<&Edids>, matches: Option<&EdidMatch>, detected: &Output) -> bool {
+    let by_edid = match (edids, detected.edid_str()) {
+        (Some(edids), Some(edid)) => edids.contains(edid),
+        _ => false,
+    };
+    let by_fields = match (matches, &detected.info) {
+        (Some(m), Some(info)) => m.matches(info),
+        _ => false,
+    };
+    by_edid || by_fields
+}
+
+/// Whether the detected output can fill the given profile output.
+pub fn output_matches(profile_output: &Output, detected: &Output) -> bool {
+    let by_connector = match &profile_output.connector {
+        Some(connector) => [&detected.output_name, &detected.path, &detected.connector_id]
+            .into_iter()
+            .flatten()
+            .any(|name| connector.matches(name)),
+        None => true,
+    };
+    // a connector alone is enough for monitors without an EDID
+    let by_monitor = match (&profile_output.edid, &profile_output.matches) {
+        (None, None) => profile_output.connector.is_some(),
+        (edids, matches) => identifies(edids.as_ref(), matches.as_ref(), detected),
+    };
+    by_connector && by_monitor
+}
+
+/// A connector name split into runs of digits, as numbers, and runs of other characters, so that
+/// names sort by their key in natural order: "DP-2" before "DP-10".
+fn natural_key(name: &str) -> Vec<(Option<u64>, &str)> {
+    let mut key = Vec::new();
+    let mut rest = name;
+    while let Some(first) = rest.chars().next() {
+        let digits = first.is_ascii_digit();
+        let end = rest.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(rest.len());
+        let (run, tail) = rest.split_at(end);
+        key.push((run.parse().ok().filter(|_| digits), run));
+        rest = tail;
+    }
+    key
+}
+
+/// A profile chosen by `Matcher::select` along with its assignment of detected outputs.
+pub struct Selection<'p, 'a> {
+    pub profile: &'p Profile,
+    pub assignment: BTreeMap<String, &'a Output>,
+}
+
+/// Assigns detected outputs to the outputs of a profile.
+pub struct Matcher<'a> {
+    detected: Vec<&'a Output>,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new<I: IntoIterator<Item = &'a Output>>(detected: I) -> Matcher<'a> {
+        let mut detected = detected.into_iter().collect::<Vec<&Output>>();
+        let name = |o: &'a Output| natural_key(o.output_name.as_deref().unwrap_or_default());
+        detected.sort_by(|a, b| name(a).cmp(&name(b)));
+        Matcher { detected }
+    }
+
+    /// Pick a distinct detected output for every required profile output that identifies a
+    /// monitor (by `edid`, `match`, or `connector`), and for as many optional ones as possible, or
+    /// `None` if that isn't possible. The result maps profile output names
+    /// to detected outputs. Profile outputs that could each fill several identical monitors get
+    /// them in connector name order, following the order of the profile output names.
+    pub fn assign(&self, profile: &Profile) -> Option<BTreeMap<String, &'a Output>> {
+        let mut slots = profile
+            .outputs
+            .iter()
+            .filter(|(_, o)| o.identifies_monitor())
+            .map(|(name, o)| {
+                let mut candidates = self
+                    .detected
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, d)| output_matches(o, d))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<usize>>();
+                if let Some(index) = o.index {
+                    candidates = candidates.get(index).into_iter().copied().collect();
+                }
+                (name.as_str(), o.optional, candidates)
+            })
+            .collect::<Vec<(&str, bool, Vec<usize>)>>();
+        // required outputs first, then those with the fewest candidates to keep backtracking
+        // shallow
+        slots.sort_by_key(|(_, optional, candidates)| (*optional, candidates.len()));
+        log::debug!("{}: {:?}", profile.name(), slots);
+
+        let mut assignment = BTreeMap::new();
+        if !assign_slots(&slots, &mut assignment) {
+            return None;
+        }
+        Some(
+            assignment
+                .into_iter()
+                .map(|(name, i)| (name.to_string(), self.detected[i]))
+                .collect(),
+        )
+    }
+
+    /// Choose the best applicable profile: highest priority first, then the one covering the most
+    /// detected outputs, then the one with the fewest optional outputs left unfilled, then by
+    /// name. Profiles that don't identify any monitor (eg "mirror") are
+    /// never selected.
+    pub fn select<'p>(&self, profiles: &'p [Profile]) -> Option<Selection<'p, 'a>> {
+        let mut candidates = profiles
+            .iter()
+            .filter_map(|profile| {
+                self.assign(profile).map(|assignment| Selection {
+                    profile,
+                    assignment,
+                })
+            })
+            .filter(|c| !c.assignment.is_empty())
+            .collect::<Vec<Selection>>();
+        candidates.sort_by(|a, b| {
+            b.profile
+                .priority()
+                .cmp(&a.profile.priority())
+                .then(b.assignment.len().cmp(&a.assignment.len()))
+                .then(unfilled(a.profile, &a.assignment).cmp(&unfilled(b.profile, &b.assignment)))
+                .then(a.profile.name().cmp(b.profile.name()))
+        });
+
+        for c in &candidates {
+            log::debug!(
+                "candidate '{}': priority {}, {} outputs, {} optional outputs missing",
+                c.profile.name(),
+                c.profile.priority(),
+                c.assignment.len(),
+                unfilled(c.profile, &c.assignment)
+            );
+        }
+        if let [first, second, ..] = candidates.as_slice() {
+            if first.profile.priority() == second.profile.priority()
+                && first.assignment.len() == second.assignment.len()
+                && unfilled(first.profile, &first.assignment) == unfilled(second.profile, &second.assignment)
+            {
+                log::info!(
+                    "profiles '{}' and '{}' tie at priority {} with {} outputs; choosing '{}' by name",
+                    first.profile.name(),
+                    second.profile.name(),
+                    first.profile.priority(),
+                    first.assignment.len(),
+                    first.profile.name(),
+                );
+            }
+        }
+
+        candidates.into_iter().next()
+    }
+}
+
+/// How closely the live layout matches a profile.
+#[derive(Debug, Default)]
+pub struct Similarity {
+    /// Number of settings that match, out of `checked`.
+    pub matched: usize,
+    pub checked: usize,
+    /// Descriptions of the settings that don't match.
+    pub differences: Vec<String>,
+}
+
+impl Similarity {
+    pub fn is_exact(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    fn check(&mut self, ok: bool, difference: impl FnOnce() -> String) {
+        self.checked += 1;
+        if ok {
+            self.matched += 1;
+        } else {
+            self.differences.push(difference());
+        }
+    }
+}
+
+/// Compare the live layout of the `active` and connected but `inactive` outputs with `profile`,
+/// given its `assignment` of detected outputs and the `positions` worked out for its relatively
+/// placed outputs.
+pub fn compare(
+    profile: &Profile,
+    assignment: &BTreeMap<String, &Output>,
+    positions: &BTreeMap<String, (i32, i32)>,
+    active: &[&Output],
+    inactive: &[&Output],
+) -> Similarity {
+    let mut similarity = Similarity::default();
+    let is_active = |o: &Output| active.iter().any(|a| a.output_name == o.output_name);
+    let declares_primary = profile.outputs.values().any(|o| o.settings.primary);
+
+    for (slot, detected) in assignment {
+        let wanted = &profile.outputs[slot].settings;
+        let live = &detected.settings;
+        let name = detected.output_name.as_deref().unwrap_or(slot);
+        if wanted.off {
+            similarity.check(!is_active(detected), || format!("{} is on", name));
+            continue;
+        }
+        if !is_active(detected) {
+            similarity.check(false, || format!("{} is off", name));
+            continue;
+        }
+
+        if let Some(mode) = &wanted.mode {
+            let live_mode = live
+                .mode
+                .as_ref()
+                .and_then(|m| detected.modes.iter().find(|dm| dm.name == *m));
+            let same = live.mode.as_ref() == Some(mode)
+                || live_mode.is_some_and(|m| format!("{}x{}", m.width, m.height) == *mode);
+            similarity.check(same, || {
+                format!("{} has mode {}, not {}", name, live.mode.as_deref().unwrap_or("none"), mode)
+            });
+        }
+        if let Some(rate) = wanted.rate {
+            let same = live.rate.is_some_and(|r| r.0.abs_diff(rate.0) <= 10);
+            similarity.check(same, || format!("{} is not at {}Hz", name, rate));
+        }
+        let pos = positions
+            .get(slot)
+            .map(|(x, y)| Position { x: *x, y: *y })
+            .or(wanted.pos);
+        if let Some(pos) = pos {
+            similarity.check(live.pos == Some(pos), || {
+                let at = live.pos.map(|p| p.to_string()).unwrap_or_default();
+                format!("{} is at {}, not {}", name, at, pos)
+            });
+        }
+        let rotate = wanted.rotate.unwrap_or(Rotate::Normal);
+        let live_rotate = live.rotate.unwrap_or(Rotate::Normal);
+        similarity.check(live_rotate == rotate, || {
+            format!("{} is rotated {}, not {}", name, live_rotate.name(), rotate.name())
+        });
+        if declares_primary {
+            similarity.check(live.primary == wanted.primary, || {
+                let not = if wanted.primary { "not " } else { "" };
+                format!("{} is {}primary", name, not)
+            });
+        }
+    }
+
+    // unlisted outputs must be off or lit as the profile's policy says; where exactly the lit ones
+    // are isn't checked
+    let policy = profile.unlisted_outputs();
+    let listed = |o: &Output| assignment.values().any(|a| a.output_name == o.output_name);
+    for output in active.iter().chain(inactive).filter(|o| !listed(o)) {
+        let name = output.output_name.as_deref().unwrap_or("");
+        let lit = is_active(output);
+        match policy {
+            UnlistedOutputs::Keep => {}
+            UnlistedOutputs::Off => {
+                similarity.check(!lit, || format!("{} is on but not part of the profile", name))
+            }
+            UnlistedOutputs::AutoExtend | UnlistedOutputs::MirrorPrimary => similarity.check(lit, || {
+                format!("{} is off but the profile's {} policy uses it", name, policy.name())
+            }),
+        }
+    }
+
+    similarity
+}
+
+/// Extend `assignment` with distinct candidates for `slots`, filling every required slot and as
+/// many optional ones as possible. Returns whether the required slots could all be filled.
+fn assign_slots<'s>(
+    slots: &[(&'s str, bool, Vec<usize>)],
+    assignment: &mut BTreeMap<&'s str, usize>,
+) -> bool {
+    let ((name, optional, candidates), rest) = match slots.split_first() {
+        Some(split) => split,
+        None => return true,
+    };
+    let complete = assignment.len() + slots.len();
+    let free = candidates
+        .iter()
+        .filter(|c| !assignment.values().any(|i| i == *c))
+        .map(|c| Some(*c));
+    // an optional slot may also be left empty
+    let choices = free.chain(optional.then_some(None));
+
+    let mut best: Option<BTreeMap<&str, usize>> = None;
+    for choice in choices {
+        let mut attempt = assignment.clone();
+        if let Some(candidate) = choice {
+            attempt.insert(name, candidate);
+        }
+        if !assign_slots(rest, &mut attempt) || best.as_ref().is_some_and(|b| b.len() >= attempt.len()) {
+            continue;
+        }
+        let filled = attempt.len() == complete;
+        best = Some(attempt);
+        if filled {
+            break;
+        }
+    }
+    match best {
+        Some(best) => {
+            *assignment = best;
+            true
+        }
+        None => false,
+    }
+}
+
+/// The number of optional outputs of `profile` that `assignment` leaves without a monitor.
+fn unfilled(profile: &Profile, assignment: &BTreeMap<String, &Output>) -> usize {
+    profile
+        .outputs
+        .iter()
+        .filter(|(name, o)| o.optional && o.identifies_monitor() && !assignment.contains_key(*name))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::edid::tests::edid_bytes;
+
+    fn profile(name: &str, priority: Option<i32>, slots: &[&Output]) -> Profile {
+        let outputs = slots
+            .iter()
+            .map(|o| {
+                let slot = Output {
+                    edid: o.edid.clone(),
+                    ..Default::default()
+                };
+                (o.output_name.clone().unwrap(), slot)
+            })
+            .collect();
+        let mut p = Profile::new(name, outputs);
+        p.priority = priority;
+        p
+    }
+
+    fn detected(name: &str, edid: Vec<u8>) -> Output {
+        Output {
+            output_name: Some(name.into()),
+            edid: Some(hex::encode(&edid).into()),
+            info: Edid::parse(&edid).ok(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(Glob::new("DELL U27*").matches("dell u2720q"));
+        assert!(Glob::new("U2?20Q").matches("U2720Q"));
+        assert!(Glob::new("*").matches(""));
+        assert!(Glob::new("a*b*c").matches("aXXbYc"));
+        assert!(!Glob::new("a*b*c").matches("aXXbY"));
+        assert!(!Glob::new("U27").matches("U2720Q"));
+    }
+
+    #[test]
+    fn assign_edid_alternatives() {
+        let outputs = [
+            detected("eDP-1", edid_bytes("AUO", 1, 0, "laptop")),
+            detected("DP-1", edid_bytes("DEL", 2, 0, "left")),
+            detected("DP-2", edid_bytes("DEL", 3, 0, "right")),
+        ];
+        let [aa, bb, cc] = [0, 1, 2].map(|i| outputs[i].edid_str().unwrap().to_string());
+        let profile: Profile = serde_yaml::from_str(&format!(
+            "outputs:\n  laptop:\n    edid: {}\n  left:\n    edid: [{}, {}]\n  right:\n    edid: [{}]\n",
+            aa, bb, cc, cc
+        ))
+        .unwrap();
+
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        assert_eq!(assignment["laptop"].output_name.as_deref(), Some("eDP-1"));
+        assert_eq!(assignment["left"].output_name.as_deref(), Some("DP-1"));
+        assert_eq!(assignment["right"].output_name.as_deref(), Some("DP-2"));
+
+        // DP-2 can't fill both left and right
+        assert!(Matcher::new(&outputs[..1]).assign(&profile).is_none());
+        assert!(Matcher::new([&outputs[0], &outputs[2]]).assign(&profile).is_none());
+    }
+
+    #[test]
+    fn assign_by_edid_fields() {
+        let outputs = [
+            detected("DP-1", edid_bytes("DEL", 0xa0f4, 7, "DELL U2720Q")),
+            detected("HDMI-1", edid_bytes("GSM", 0x5b7f, 9, "LG HDR 4K")),
+        ];
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+outputs:
+  desk:
+    match:
+      vendor: del
+      name: "DELL U27*"
+  tv:
+    match:
+      product: "5B7?"
+"#,
+        )
+        .unwrap();
+
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        assert_eq!(assignment["desk"].output_name.as_deref(), Some("DP-1"));
+        assert_eq!(assignment["tv"].output_name.as_deref(), Some("HDMI-1"));
+
+        let missing_serial: Profile =
+            serde_yaml::from_str("outputs:\n  desk:\n    match:\n      serial: \"XYZ*\"\n").unwrap();
+        assert!(Matcher::new(&outputs).assign(&missing_serial).is_none());
+    }
+
+    #[test]
+    fn assign_identical_monitors() {
+        let edid = edid_bytes("ACR", 4, 0, "cheap");
+        let mut outputs = [detected("DP-2", edid.clone()), detected("DP-1", edid.clone())];
+        outputs[0].path = Some("mst:63-2".into());
+        outputs[0].connector_id = Some("96".into());
+        let edid = outputs[0].edid_str().unwrap().to_string();
+        let assign = |yaml: &str| {
+            let profile: Profile = serde_yaml::from_str(&yaml.replace("EDID", &edid)).unwrap();
+            Matcher::new(&outputs)
+                .assign(&profile)
+                .map(|a| {
+                    a.into_iter()
+                        .map(|(slot, o)| format!("{}={}", slot, o.output_name.as_ref().unwrap()))
+                        .collect::<Vec<String>>()
+                })
+        };
+        let pairs = |p: &[&str]| Some(p.iter().map(|s| s.to_string()).collect::<Vec<String>>());
+
+        // in connector order
+        assert_eq!(
+            assign("outputs:\n  a:\n    edid: EDID\n  b:\n    edid: EDID\n"),
+            pairs(&["a=DP-1", "b=DP-2"])
+        );
+        assert_eq!(
+            assign("outputs:\n  a:\n    edid: EDID\n    connector: DP-2\n  b:\n    edid: EDID\n"),
+            pairs(&["a=DP-2", "b=DP-1"])
+        );
+        assert_eq!(
+            assign("outputs:\n  a:\n    edid: EDID\n  b:\n    edid: EDID\n    connector: \"mst:*-2\"\n"),
+            pairs(&["a=DP-1", "b=DP-2"])
+        );
+        assert_eq!(
+            assign("outputs:\n  a:\n    edid: EDID\n    index: 1\n  b:\n    edid: EDID\n    index: 0\n"),
+            pairs(&["a=DP-2", "b=DP-1"])
+        );
+        assert_eq!(
+            assign("outputs:\n  a:\n    edid: EDID\n  b:\n    edid: EDID\n    connector: \"96\"\n"),
+            pairs(&["a=DP-1", "b=DP-2"])
+        );
+        assert_eq!(assign("outputs:\n  a:\n    edid: EDID\n    index: 2\n"), None);
+    }
+
+    #[test]
+    fn natural_connector_order() {
+        let mut names = ["DP-10", "eDP-1", "DP-2", "DP-1-10", "HDMI-1", "DP-1-2"];
+        names.sort_by(|a, b| natural_key(a).cmp(&natural_key(b)));
+        assert_eq!(names, ["DP-1-2", "DP-1-10", "DP-2", "DP-10", "HDMI-1", "eDP-1"]);
+
+        let edid = edid_bytes("ACR", 4, 0, "cheap");
+        let outputs = [detected("DP-10", edid.clone()), detected("DP-2", edid.clone())];
+        let profile: Profile = serde_yaml::from_str(&format!(
+            "outputs:\n  a:\n    edid: {0}\n  b:\n    edid: {0}\n",
+            outputs[0].edid_str().unwrap()
+        ))
+        .unwrap();
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        assert_eq!(assignment["a"].output_name.as_deref(), Some("DP-2"));
+        assert_eq!(assignment["b"].output_name.as_deref(), Some("DP-10"));
+    }
+
+    #[test]
+    fn assign_by_connector_without_edid() {
+        let laptop = detected("eDP-1", edid_bytes("AUO", 1, 0, "laptop"));
+        let projector = Output {
+            output_name: Some("HDMI-1".into()),
+            connected: true,
+            ..Default::default()
+        };
+        let outputs = [laptop.clone(), projector];
+        let profile: Profile = serde_yaml::from_str(&format!(
+            "outputs:\n  laptop:\n    edid: {}\n  projector:\n    connector: HDMI-*\n",
+            laptop.edid_str().unwrap()
+        ))
+        .unwrap();
+
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        assert_eq!(assignment["projector"].output_name.as_deref(), Some("HDMI-1"));
+        assert!(Matcher::new(&outputs[..1]).assign(&profile).is_none());
+    }
+
+    #[test]
+    fn assign_optional_outputs() {
+        let outputs = [
+            detected("DP-1", edid_bytes("DEL", 2, 0, "desk")),
+            detected("DP-2", edid_bytes("WAC", 5, 0, "tablet")),
+            detected("DP-3", edid_bytes("DEL", 2, 0, "desk")),
+        ];
+        let [desk, tablet] = [0, 1].map(|i| outputs[i].edid_str().unwrap().to_string());
+        let optional: Profile = serde_yaml::from_str(&format!(
+            "outputs:\n  desk:\n    edid: {}\n  tablet:\n    edid: {}\n    optional: true\n  spare:\n    edid: {}\n    optional: true\n",
+            desk, tablet, desk
+        ))
+        .unwrap();
+        let slots = |a: Option<BTreeMap<String, &Output>>| a.map(|a| a.into_keys().collect::<Vec<String>>());
+
+        assert_eq!(slots(Matcher::new(&outputs[..1]).assign(&optional)), Some(vec!["desk".into()]));
+        assert_eq!(
+            slots(Matcher::new(&outputs).assign(&optional)),
+            Some(vec!["desk".into(), "spare".into(), "tablet".into()])
+        );
+        assert_eq!(slots(Matcher::new(&outputs[1..2]).assign(&optional)), None);
+
+        // the profile with its optional output filled wins, and otherwise loses to the plain one
+        let profiles = [
+            Profile::new("a-optional", optional.outputs),
+            profile("plain", None, &[&outputs[0]]),
+        ];
+        let matcher = Matcher::new(&outputs[..2]);
+        assert_eq!(matcher.select(&profiles).unwrap().profile.name(), "a-optional");
+        let matcher = Matcher::new(&outputs[..1]);
+        assert_eq!(matcher.select(&profiles).unwrap().profile.name(), "plain");
+    }
+
+    #[test]
+    fn select_most_specific_then_priority() {
+        let outputs = [
+            detected("eDP-1", edid_bytes("AUO", 1, 0, "laptop")),
+            detected("DP-1", edid_bytes("DEL", 2, 0, "left")),
+            detected("DP-2", edid_bytes("DEL", 3, 0, "right")),
+        ];
+        let matcher = Matcher::new(&outputs);
+
+        let mut profiles = vec![
+            profile("a-laptop", None, &[&outputs[0]]),
+            profile("home-desk", None, &[&outputs[0], &outputs[1], &outputs[2]]),
+            profile("office", None, &[&outputs[0], &outputs[1], &detected("X", edid_bytes("XXX", 9, 0, "x"))]),
+        ];
+        assert_eq!(matcher.select(&profiles).unwrap().profile.name(), "home-desk");
+
+        profiles[0].priority = Some(1);
+        assert_eq!(matcher.select(&profiles).unwrap().profile.name(), "a-laptop");
+
+        profiles[0].priority = None;
+        profiles.push(profile("b-desk", None, &[&outputs[2], &outputs[1], &outputs[0]]));
+        assert_eq!(matcher.select(&profiles).unwrap().profile.name(), "b-desk");
+    }
+
+    #[test]
+    fn compare_unlisted_outputs_by_policy() {
+        let laptop = detected("eDP-1", edid_bytes("AUO", 1, 0, "laptop"));
+        let tv = detected("HDMI-1", edid_bytes("GSM", 2, 0, "tv"));
+        let check = |policy: &str, tv_lit: bool| {
+            let profile: Profile = serde_yaml::from_str(&format!(
+                "unlisted_outputs: {}\noutputs:\n  laptop:\n    edid: {}\n",
+                policy,
+                laptop.edid_str().unwrap()
+            ))
+            .unwrap();
+            let outputs = [&laptop, &tv];
+            let assignment = Matcher::new(outputs).assign(&profile).unwrap();
+            let (active, inactive): (Vec<&Output>, Vec<&Output>) = match tv_lit {
+                true => (vec![&laptop, &tv], vec![]),
+                false => (vec![&laptop], vec![&tv]),
+            };
+            compare(&profile, &assignment, &BTreeMap::new(), &active, &inactive).differences
+        };
+
+        assert_eq!(check("off", true), ["HDMI-1 is on but not part of the profile"]);
+        assert!(check("off", false).is_empty());
+        assert!(check("keep", true).is_empty());
+        assert!(check("keep", false).is_empty());
+        assert!(check("auto-extend", true).is_empty());
+        assert_eq!(
+            check("mirror-primary", false),
+            ["HDMI-1 is off but the profile's mirror-primary policy uses it"]
+        );
+    }
+
+    #[test]
+    fn compare_live_layout_with_profile() {
+        let mut laptop = detected("eDP-1", edid_bytes("AUO", 1, 0, "laptop"));
+        let mut desk = detected("DP-1", edid_bytes("DEL", 2, 0, "desk"));
+        let live = |o: &mut Output, yaml: &str| o.settings = serde_yaml::from_str(yaml).unwrap();
+        live(&mut laptop, "{mode: 1920x1080, pos: 0x0, rotate: normal, primary: true}");
+        live(&mut desk, "{mode: 2560x1440, pos: 1920x0, rotate: normal}");
+
+        let profile: Profile = serde_yaml::from_str(&format!(
+            "outputs:\n  laptop:\n    edid: {}\n    settings: {{mode: 1920x1080, primary: true}}\n  desk:\n    edid: {}\n    settings: {{mode: 2560x1440}}\n    placement:\n      right-of: laptop\n",
+            laptop.edid_str().unwrap(),
+            desk.edid_str().unwrap(),
+        ))
+        .unwrap();
+        let outputs = [laptop.clone(), desk.clone()];
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        let positions = BTreeMap::from([("desk".to_string(), (1920, 0)), ("laptop".to_string(), (0, 0))]);
+
+        let similarity = compare(&profile, &assignment, &positions, &[&outputs[0], &outputs[1]], &[]);
+        assert!(similarity.is_exact(), "{:?}", similarity.differences);
+        assert_eq!((similarity.matched, similarity.checked), (8, 8));
+
+        live(&mut desk, "{mode: 1920x1080, pos: 1920x0, rotate: left}");
+        let outputs = [laptop, desk];
+        let assignment = Matcher::new(&outputs).assign(&profile).unwrap();
+        let similarity = compare(&profile, &assignment, &positions, &[&outputs[0], &outputs[1]], &[]);
+        assert_eq!(
+            similarity.differences,
+            [
+                "DP-1 has mode 1920x1080, not 2560x1440",
+                "DP-1 is rotated left, not normal",
+            ]
+        );
+        assert_eq!(similarity.matched, 6);
+
+        let similarity = compare(&profile, &assignment, &positions, &[&outputs[0]], &[&outputs[1]]);
+        assert_eq!(similarity.differences, ["DP-1 is off"]);
+    }
+}
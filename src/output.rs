@@ -1,55 +1,686 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
 
 use hex::encode;
 use serde::{Deserialize, Serialize};
 use xrandr::{Output as XRandrOutput, Value};
 
+use crate::edid::Edid;
+use crate::errors::{Error, Result};
+use crate::matcher::{EdidMatch, Glob};
+use crate::placement::Placement;
+
+/// Largest resolution assumed to work on an output that doesn't report a preferred mode.
+const FALLBACK_RESOLUTION: (u32, u32) = (1920, 1080);
+
+/// The EDID(s) that can fill a profile output: either a single value or a list of acceptable
+/// alternatives.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(untagged)]
+pub enum Edids {
+    One(String),
+    AnyOf(Vec<String>),
+}
+
+impl Edids {
+    /// Whether `edid` is one of the acceptable values.
+    pub fn contains(&self, edid: &str) -> bool {
+        self.iter().any(|e| e == edid)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        match self {
+            Edids::One(e) => std::slice::from_ref(e).iter(),
+            Edids::AnyOf(es) => es.iter(),
+        }
+    }
+
+    /// The first (for detected outputs, the only) value.
+    pub fn first(&self) -> Option<&str> {
+        self.iter().next().map(String::as_str)
+    }
+}
+
+impl From<String> for Edids {
+    fn from(s: String) -> Edids {
+        Edids::One(s)
+    }
+}
+
+impl fmt::Display for Edids {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edids::One(e) => write!(f, "{}", e),
+            Edids::AnyOf(es) => write!(f, "any of [{}]", es.join(", ")),
+        }
+    }
+}
+
+/// Error message for a setting that failed to parse.
+fn invalid(setting: &str, value: &str) -> Error {
+    Error::InvalidSetting(setting.into(), value.into())
+}
+
+fn parse_pair<T: FromStr>(s: &str) -> Option<(T, T)> {
+    let (a, b) = s.split_once('x')?;
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+/// Refresh rate, kept in mHz so that settings can be compared exactly.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Rate(pub u32);
+
+impl Rate {
+    pub fn hz(&self) -> f64 {
+        self.0 as f64 / 1000.0
+    }
+}
+
+impl TryFrom<f64> for Rate {
+    type Error = Error;
+
+    fn try_from(hz: f64) -> Result<Rate> {
+        if !(hz > 0.0 && hz < 1000.0) {
+            return Err(invalid("rate", &hz.to_string()));
+        }
+        Ok(Rate((hz * 1000.0).round() as u32))
+    }
+}
+
+impl From<Rate> for f64 {
+    fn from(rate: Rate) -> f64 {
+        rate.hz()
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.hz())
+    }
+}
+
+/// A position on the screen, written `<x>x<y>`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl FromStr for Position {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Position> {
+        let (x, y) = parse_pair(s).ok_or_else(|| invalid("pos", s))?;
+        Ok(Position { x, y })
+    }
+}
+
+impl TryFrom<String> for Position {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Position> {
+        s.parse()
+    }
+}
+
+impl From<Position> for String {
+    fn from(p: Position) -> String {
+        p.to_string()
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.x, self.y)
+    }
+}
+
+/// A size in pixels, written `<width>x<height>`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Size {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Size> {
+        match parse_pair(s) {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Size { width, height }),
+            _ => Err(invalid("size", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Size {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Size> {
+        s.parse()
+    }
+}
+
+impl From<Size> for String {
+    fn from(s: Size) -> String {
+        s.to_string()
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotate {
+    Normal,
+    Left,
+    Inverted,
+    Right,
+}
+
+impl Rotate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rotate::Normal => "normal",
+            Rotate::Left => "left",
+            Rotate::Inverted => "inverted",
+            Rotate::Right => "right",
+        }
+    }
+
+    /// Whether the output's width and height trade places on the screen.
+    pub fn is_sideways(&self) -> bool {
+        matches!(self, Rotate::Left | Rotate::Right)
+    }
+}
+
+impl FromStr for Rotate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Rotate> {
+        match s {
+            "normal" => Ok(Rotate::Normal),
+            "left" => Ok(Rotate::Left),
+            "inverted" => Ok(Rotate::Inverted),
+            "right" => Ok(Rotate::Right),
+            _ => Err(invalid("rotate", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Reflect {
+    Normal,
+    X,
+    Y,
+    Xy,
+}
+
+impl Reflect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reflect::Normal => "normal",
+            Reflect::X => "x",
+            Reflect::Y => "y",
+            Reflect::Xy => "xy",
+        }
+    }
+}
+
+impl FromStr for Reflect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Reflect> {
+        match s {
+            "normal" => Ok(Reflect::Normal),
+            "x" => Ok(Reflect::X),
+            "y" => Ok(Reflect::Y),
+            "xy" => Ok(Reflect::Xy),
+            _ => Err(invalid("reflect", s)),
+        }
+    }
+}
+
+/// Horizontal and vertical scaling factors in thousandths, written either as a single factor or
+/// as `<x>x<y>`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "ScaleValue", into = "String")]
+pub struct Scale {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Scale {
+    pub fn factors(&self) -> (f64, f64) {
+        (self.x as f64 / 1000.0, self.y as f64 / 1000.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleValue {
+    Uniform(f64),
+    Pair(String),
+}
+
+impl FromStr for Scale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Scale> {
+        let (x, y) = match s.parse::<f64>() {
+            Ok(f) => (f, f),
+            Err(_) => parse_pair(s).ok_or_else(|| invalid("scale", s))?,
+        };
+        if !(x > 0.0 && y > 0.0) {
+            return Err(invalid("scale", s));
+        }
+        Ok(Scale {
+            x: (x * 1000.0).round() as u32,
+            y: (y * 1000.0).round() as u32,
+        })
+    }
+}
+
+impl TryFrom<ScaleValue> for Scale {
+    type Error = Error;
+
+    fn try_from(v: ScaleValue) -> Result<Scale> {
+        match v {
+            ScaleValue::Uniform(f) => f.to_string().parse(),
+            ScaleValue::Pair(s) => s.parse(),
+        }
+    }
+}
+
+impl From<Scale> for String {
+    fn from(s: Scale) -> String {
+        s.to_string()
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y) = self.factors();
+        write!(f, "{}x{}", x, y)
+    }
+}
+
+/// A 3x3 transformation matrix, written as nine comma-separated numbers in row-major order as
+/// for `xrandr --transform`, or "none".
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct Transform(String);
+
+impl Transform {
+    pub fn matrix(&self) -> [[f64; 3]; 3] {
+        let mut matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+        if self.0 != "none" {
+            for (i, v) in self.0.split(',').enumerate() {
+                matrix[i / 3][i % 3] = v.trim().parse().unwrap_or_default();
+            }
+        }
+        matrix
+    }
+}
+
+/// The size of the area that an output of size `width`x`height` covers on the screen under
+/// `matrix`.
+pub(crate) fn transformed_size(matrix: [[f64; 3]; 3], width: u32, height: u32) -> (u32, u32) {
+    let (w, h) = (width as f64, height as f64);
+    let corners = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)].map(|(x, y)| {
+        let [a, b, c] = matrix;
+        let z = c[0] * x + c[1] * y + c[2];
+        ((a[0] * x + a[1] * y + a[2]) / z, (b[0] * x + b[1] * y + b[2]) / z)
+    });
+    let span = |values: [f64; 4]| {
+        let max = values.iter().copied().fold(f64::MIN, f64::max);
+        let min = values.iter().copied().fold(f64::MAX, f64::min);
+        (max - min).round() as u32
+    };
+    (span(corners.map(|c| c.0)), span(corners.map(|c| c.1)))
+}
+
+impl FromStr for Transform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Transform> {
+        let values = s.split(',').map(|v| v.trim().parse::<f64>()).collect::<Vec<_>>();
+        if s != "none" && (values.len() != 9 || values.iter().any(|v| v.is_err())) {
+            return Err(invalid("transform", s));
+        }
+        Ok(Transform(s.into()))
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Transform> {
+        s.parse()
+    }
+}
+
+impl From<Transform> for String {
+    fn from(t: Transform) -> String {
+        t.0
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Settings for an output, validated when a profile is loaded.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSettings {
+    /// Mode name, or `<width>x<height>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Rate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pos: Option<Position>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<Rotate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reflect: Option<Reflect>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Scale>,
+    /// Scale the output so that it shows an area of this size.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_from: Option<Size>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform: Option<Transform>,
+    /// Whether this output should be (or currently is) the primary output.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub primary: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub off: bool,
+    /// Use the preferred mode, unless `mode` is given.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub auto: bool,
+}
+
+impl OutputSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == OutputSettings::default()
+    }
+
+    /// The equivalent `xrandr --output` arguments.
+    pub fn to_args(&self) -> Vec<String> {
+        if self.off {
+            return vec!["--off".into()];
+        }
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: Option<String>| {
+            args.push(flag.to_string());
+            args.extend(value);
+        };
+        if self.auto {
+            push("--auto", None);
+        }
+        if let Some(mode) = &self.mode {
+            push("--mode", Some(mode.clone()));
+        }
+        if let Some(rate) = self.rate {
+            push("--rate", Some(rate.to_string()));
+        }
+        if let Some(pos) = self.pos {
+            push("--pos", Some(pos.to_string()));
+        }
+        if let Some(rotate) = self.rotate {
+            push("--rotate", Some(rotate.name().into()));
+        }
+        if let Some(reflect) = self.reflect {
+            push("--reflect", Some(reflect.name().into()));
+        }
+        if let Some(scale) = self.scale {
+            push("--scale", Some(scale.to_string()));
+        }
+        if let Some(size) = self.scale_from {
+            push("--scale-from", Some(size.to_string()));
+        }
+        if let Some(transform) = &self.transform {
+            push("--transform", Some(transform.to_string()));
+        }
+        if self.primary {
+            push("--primary", None);
+        }
+        args
+    }
+
+    /// Take the setting for an `xrandr --output` argument, returning `false` if there is no typed
+    /// setting for it.
+    fn set_arg(&mut self, arg: &str, value: &str) -> Result<bool> {
+        let flag = |value: &str| match value {
+            "" | "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(invalid(arg, value)),
+        };
+        match arg {
+            "--mode" => self.mode = Some(value.into()),
+            "--rate" | "--refresh" => {
+                let hz = value.parse::<f64>().map_err(|_| invalid("rate", value))?;
+                self.rate = Some(Rate::try_from(hz)?);
+            }
+            "--pos" => self.pos = Some(value.parse()?),
+            "--rotate" | "--rotation" => self.rotate = Some(value.parse()?),
+            "--reflect" => self.reflect = Some(value.parse()?),
+            "--scale" => self.scale = Some(value.parse()?),
+            "--scale-from" => self.scale_from = Some(value.parse()?),
+            "--transform" => self.transform = Some(value.parse()?),
+            "--primary" => self.primary = flag(value)?,
+            "--off" => self.off = flag(value)?,
+            "--auto" => self.auto = flag(value)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// A mode supported by a detected output.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct OutputMode {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Refresh rate in mHz.
+    pub refresh: u32,
+    pub preferred: bool,
+}
+
 /// A display device representation.
-#[derive(Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(deny_unknown_fields)]
 pub struct Output {
     pub output_name: Option<String>,
-    // TODO: make edid value an enum with variants that allow for multiple possible monitors in
-    // profiles (allows a profile with some fixed screens, some dynamic -- eg, the position of one
-    // output could be one of multiple outputs)
-    pub edid: Option<String>,
+    pub edid: Option<Edids>,
+    /// Match a monitor by its decoded EDID fields rather than the exact EDID.
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matches: Option<EdidMatch>,
+    /// Alias of a known monitor (see `Config.monitors`) that supplies `edid` and `match`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+    /// Connector the monitor must be plugged into, matched against the connector name (eg
+    /// "DP-1") and its RandR `PATH` or `CONNECTOR_ID` property. Tells apart monitors whose EDIDs
+    /// are identical, and on its own identifies monitors that have no EDID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector: Option<Glob>,
+    /// Which of the detected monitors identified by `edid` or `match` to use, counting from 0 in
+    /// connector name order, for identical monitors that nothing else tells apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// The profile applies whether or not this output's monitor is connected, and configures it
+    /// when it is.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    #[serde(default, skip_serializing_if = "OutputSettings::is_empty")]
+    pub settings: OutputSettings,
+    /// Extra `xrandr --output` arguments for which there is no typed setting. Arguments that do
+    /// have one are moved into `settings` when a profile is loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub xrandr_args: Option<BTreeMap<String, String>>,
+    /// Position relative to another output of the profile; overrides `pos`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub placement: Option<Placement>,
+    /// Fields decoded from the EDID of a detected output.
+    #[serde(skip)]
+    pub info: Option<Edid>,
+    /// RandR `PATH` property of a detected output.
+    #[serde(skip)]
+    pub path: Option<String>,
+    /// RandR `CONNECTOR_ID` property of a detected output.
+    #[serde(skip)]
+    pub connector_id: Option<String>,
+    /// Whether RandR reports a monitor connected to a detected output, with or without an EDID.
+    #[serde(skip)]
+    pub connected: bool,
+    /// Modes supported by a detected output, in the order RandR reports them.
+    #[serde(skip)]
+    pub modes: Vec<OutputMode>,
 }
 
 impl Output {
+    /// The EDID of a detected output.
+    pub fn edid_str(&self) -> Option<&str> {
+        self.edid.as_ref().and_then(|e| e.first())
+    }
+
+    /// The preferred mode of a detected output. Outputs that report none, typically because the
+    /// monitor has no EDID, get the largest mode up to `FALLBACK_RESOLUTION`, favouring 60Hz.
+    pub fn preferred_mode(&self) -> Option<&OutputMode> {
+        let (max_width, max_height) = FALLBACK_RESOLUTION;
+        self.modes
+            .iter()
+            .find(|m| m.preferred)
+            .or_else(|| {
+                self.modes
+                    .iter()
+                    .filter(|m| m.width <= max_width && m.height <= max_height)
+                    .max_by_key(|m| (m.width * m.height, std::cmp::Reverse(m.refresh.abs_diff(60000))))
+            })
+            .or_else(|| self.modes.first())
+    }
+
+    /// Whether a profile output says which monitor fills it, by `edid`, `match`, or `connector`.
+    pub fn identifies_monitor(&self) -> bool {
+        self.edid.is_some() || self.matches.is_some() || self.connector.is_some()
+    }
+
+    /// A mode of a detected output with the given resolution, preferring the highest refresh rate.
+    pub fn mode_with_resolution(&self, width: u32, height: u32) -> Option<&OutputMode> {
+        self.modes
+            .iter()
+            .filter(|m| m.width == width && m.height == height)
+            .max_by_key(|m| m.refresh)
+    }
+
+    /// The `xrandr --output` arguments for this output: its typed settings followed by any extra
+    /// `xrandr_args`.
     pub fn get_args(&self) -> Vec<String> {
-        if let Some(args) = &self.xrandr_args {
-            args.iter()
-                .map(|(k, v)| [k.clone(), v.clone()])
-                .flat_map(|s| s)
-                .collect()
-        } else {
-            Vec::new()
+        let mut args = self.settings.to_args();
+        if self.settings.off {
+            return args;
         }
+        for (k, v) in self.xrandr_args.iter().flatten() {
+            args.push(k.clone());
+            if !v.is_empty() {
+                args.push(v.clone());
+            }
+        }
+        args
+    }
+
+    /// Move entries of `xrandr_args` that have a typed setting into `settings`, validating them.
+    pub fn lift_xrandr_args(&mut self) -> Result<()> {
+        if let Some(args) = self.xrandr_args.take() {
+            let mut rest = BTreeMap::new();
+            for (arg, value) in args {
+                if !self.settings.set_arg(&arg, &value)? {
+                    rest.insert(arg, value);
+                }
+            }
+            self.xrandr_args = Some(rest).filter(|r| !r.is_empty());
+        }
+        Ok(())
     }
 }
 
+/// The largest resolution (by area) that every one of `outputs` supports.
+pub fn largest_common_resolution(outputs: &[&Output]) -> Option<(u32, u32)> {
+    let (first, rest) = outputs.split_first()?;
+    first
+        .modes
+        .iter()
+        .map(|m| (m.width, m.height))
+        .filter(|(w, h)| rest.iter().all(|o| o.mode_with_resolution(*w, *h).is_some()))
+        .max_by_key(|(w, h)| (w * h, *w))
+}
+
 impl From<&XRandrOutput> for Output {
     fn from(o: &XRandrOutput) -> Output {
+        let edid = match o.properties.get("EDID") {
+            Some(p) => match &p.value {
+                Value::Edid(v) if !v.is_empty() => Some(v),
+                _ => None,
+            },
+            None => None,
+        };
+        let info = edid.and_then(|v| match Edid::parse(v) {
+            Ok(info) => Some(info),
+            Err(e) => {
+                log::debug!("failed to decode EDID of {}: {}", o.name, e);
+                None
+            }
+        });
+        // the PATH property is a string, which the xrandr crate doesn't decode; see `XHandleWrapper`
+        let connector_id = match o.properties.get("CONNECTOR_ID").map(|p| &p.value) {
+            Some(Value::Integer32(v)) => v.first().map(|id| id.to_string()),
+            _ => None,
+        };
         Output {
-            output_name: Some(String::from(o.name.clone())),
-            edid: match o.properties.get("EDID") {
-                Some(p) => match &p.value {
-                    Value::Edid(v) => Some(encode(v)),
-                    _ => None,
-                },
-                None => None,
+            output_name: Some(o.name.clone()),
+            edid: edid.map(|v| encode(v).into()),
+            matches: None,
+            monitor: None,
+            connector: None,
+            index: None,
+            optional: false,
+            settings: OutputSettings {
+                primary: o.is_primary,
+                ..Default::default()
             },
-            xrandr_args: Some(BTreeMap::new()),
+            xrandr_args: None,
+            placement: None,
+            info,
+            path: None,
+            connector_id,
+            connected: o.connected,
+            modes: Vec::new(),
         }
     }
 }
 
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if let Some(args) = &self.xrandr_args {
-            for (arg, value) in args {
-                write!(f, "  {0} = {1}\n", arg, value)?;
+        let args = self.get_args();
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            match args.next_if(|v| !v.starts_with("--")) {
+                Some(value) => writeln!(f, "  {0} = {1}", arg, value)?,
+                None => writeln!(f, "  {0}", arg)?,
             }
         }
         Ok(())
@@ -61,14 +692,26 @@ mod tests {
     use super::*;
 
     use indexmap::IndexMap;
-    use xrandr::{Output as XOutput, Property, PropertyValue};
+    use xrandr::{Output as XOutput, Property, Value as PropertyValue};
 
     #[test]
     fn convert_xrandr_output_to_autorandr_output() {
         let edid: Vec<u8> = Vec::from([0]);
         let xo = &XOutput {
             xid: 0,
+            timestamp: 0,
+            is_primary: false,
+            crtc: None,
             name: "MEOW-1".into(),
+            mm_width: 0,
+            mm_height: 0,
+            connected: true,
+            subpixel_order: 0,
+            crtcs: Vec::new(),
+            clones: Vec::new(),
+            modes: Vec::new(),
+            preferred_modes: Vec::new(),
+            current_mode: None,
             properties: IndexMap::from([(
                 "EDID".into(),
                 Property {
@@ -78,14 +721,125 @@ mod tests {
                     is_immutable: true,
                     is_pending: false,
                 },
+            ), (
+                "CONNECTOR_ID".into(),
+                Property {
+                    name: "CONNECTOR_ID".into(),
+                    value: PropertyValue::Integer32(vec![95]),
+                    values: None,
+                    is_immutable: true,
+                    is_pending: false,
+                },
             )]),
         };
         let expected = Output {
             output_name: Some(xo.name.clone()),
-            edid: Some("00".into()),
-            xrandr_args: BTreeMap::new(),
+            edid: Some(Edids::One("00".into())),
+            matches: None,
+            monitor: None,
+            connector: None,
+            index: None,
+            optional: false,
+            settings: OutputSettings::default(),
+            xrandr_args: None,
+            placement: None,
+            info: None,
+            path: None,
+            connector_id: Some("95".into()),
+            connected: true,
+            modes: Vec::new(),
         };
         let actual: Output = xo.into();
         assert_eq!(expected, actual);
     }
+
+    fn with_modes(modes: &[(u32, u32)]) -> Output {
+        Output {
+            modes: modes
+                .iter()
+                .map(|(w, h)| OutputMode {
+                    name: format!("{}x{}", w, h),
+                    width: *w,
+                    height: *h,
+                    refresh: 60000,
+                    preferred: false,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn find_largest_common_resolution() {
+        let laptop = with_modes(&[(2560, 1600), (1920, 1200), (1920, 1080), (1280, 720)]);
+        let projector = with_modes(&[(1920, 1080), (1280, 720), (1024, 768)]);
+        let tv = with_modes(&[(3840, 2160), (1920, 1080), (1280, 720)]);
+        let old = with_modes(&[(1024, 768), (800, 600)]);
+
+        assert_eq!(largest_common_resolution(&[&laptop, &tv]), Some((1920, 1080)));
+        assert_eq!(largest_common_resolution(&[&laptop, &projector, &tv]), Some((1920, 1080)));
+        assert_eq!(largest_common_resolution(&[&projector, &old]), Some((1024, 768)));
+        assert_eq!(largest_common_resolution(&[&laptop, &old]), None);
+    }
+
+    #[test]
+    fn preferred_mode_without_edid() {
+        let mut kvm = with_modes(&[(3840, 2160), (1920, 1080), (1280, 720)]);
+        kvm.modes.insert(1, OutputMode { refresh: 30000, ..kvm.modes[1].clone() });
+        assert_eq!(kvm.preferred_mode().map(|m| m.refresh), Some(60000));
+        assert_eq!(kvm.preferred_mode().map(|m| m.width), Some(1920));
+
+        kvm.modes[3].preferred = true;
+        assert_eq!(kvm.preferred_mode().map(|m| m.width), Some(1280));
+
+        let huge = with_modes(&[(5120, 2880)]);
+        assert_eq!(huge.preferred_mode().map(|m| m.width), Some(5120));
+    }
+
+    #[test]
+    fn typed_settings() {
+        let output: Output = serde_yaml::from_str(
+            r#"
+edid: "00ff"
+settings:
+  mode: 2560x1440
+  rate: 59.95
+  pos: 1920x0
+  rotate: left
+  scale: 1.25
+  primary: true
+xrandr_args:
+  --dpi: "144"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            output.get_args().join(" "),
+            "--mode 2560x1440 --rate 59.95 --pos 1920x0 --rotate left --scale 1.25x1.25 --primary --dpi 144"
+        );
+
+        for bad in ["rotat: left", "rotate: sideways", "pos: 10,20", "rate: -1", "scale: 0"] {
+            let yaml = format!("settings:\n  {}\n", bad);
+            assert!(serde_yaml::from_str::<Output>(&yaml).is_err(), "{}", bad);
+        }
+
+        // hex-looking positions stay positions
+        let origin: Output = serde_yaml::from_str("edid: aa\nsettings:\n  pos: 0x0\n").unwrap();
+        assert_eq!(origin.settings.pos, Some(Position { x: 0, y: 0 }));
+        let below: Output = serde_yaml::from_str("settings: {pos: 0x1080}").unwrap();
+        assert_eq!(below.settings.pos, Some(Position { x: 0, y: 1080 }));
+
+        let mut legacy: Output = serde_yaml::from_str(
+            "xrandr_args:\n  --mode: 1920x1080\n  --pos: 0x0\n  --primary: \"\"\n  --dpi: \"96\"\n",
+        )
+        .unwrap();
+        legacy.lift_xrandr_args().unwrap();
+        assert_eq!(legacy.settings.mode.as_deref(), Some("1920x1080"));
+        assert_eq!(legacy.settings.pos, Some(Position { x: 0, y: 0 }));
+        assert!(legacy.settings.primary);
+        assert_eq!(legacy.xrandr_args.unwrap().keys().collect::<Vec<&String>>(), ["--dpi"]);
+
+        let mut typo: Output = serde_yaml::from_str("xrandr_args:\n  --rotate: lef\n").unwrap();
+        assert!(matches!(typo.lift_xrandr_args(), Err(Error::InvalidSetting(..))));
+    }
 }
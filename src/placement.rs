@@ -0,0 +1,465 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::{transformed_size, Size};
+use crate::{Error, Output, OutputSettings, Profile, Result};
+
+/// Where an output sits relative to another output of the same profile, named by its profile
+/// output name rather than its connector.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "kebab-case")]
+pub enum Relation {
+    LeftOf(String),
+    RightOf(String),
+    Above(String),
+    Below(String),
+}
+
+impl Relation {
+    fn target(&self) -> &str {
+        match self {
+            Relation::LeftOf(t) | Relation::RightOf(t) | Relation::Above(t) | Relation::Below(t) => t,
+        }
+    }
+}
+
+/// How an output lines up with the output it is placed against. For outputs placed above or
+/// below, `left` and `right` may be used instead of `top` and `bottom`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    #[default]
+    #[serde(alias = "left")]
+    Top,
+    Center,
+    #[serde(alias = "right")]
+    Bottom,
+}
+
+impl Align {
+    fn offset(&self, start: i32, length: i32, own: i32) -> i32 {
+        match self {
+            Align::Top => start,
+            Align::Center => start + (length - own) / 2,
+            Align::Bottom => start + length - own,
+        }
+    }
+}
+
+/// Relative placement of a profile output, eg `{right-of: laptop, align: center}`.
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Placement {
+    #[serde(flatten)]
+    pub relation: Relation,
+    #[serde(default)]
+    pub align: Align,
+}
+
+/// An output taking part in a layout, as seen by the solver.
+#[derive(Debug)]
+pub(crate) struct Slot<'a> {
+    pub name: &'a str,
+    /// Size on the screen, after rotation and scaling.
+    pub size: (i32, i32),
+    /// Absolute position for outputs without a placement; defaults to the origin.
+    pub pos: Option<(i32, i32)>,
+    pub placement: Option<&'a Placement>,
+}
+
+#[derive(Clone, Copy)]
+struct Rect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Rect {
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    fn touches(&self, other: &Rect) -> bool {
+        let spans_x = self.x < other.x + other.w && other.x < self.x + self.w;
+        let spans_y = self.y < other.y + other.h && other.y < self.y + self.h;
+        self.overlaps(other)
+            || (spans_x && (self.y + self.h == other.y || other.y + other.h == self.y))
+            || (spans_y && (self.x + self.w == other.x || other.x + other.w == self.x))
+    }
+
+    fn same(&self, other: &Rect) -> bool {
+        (self.x, self.y, self.w, self.h) == (other.x, other.y, other.w, other.h)
+    }
+}
+
+/// `xrandr --output` arguments that position an output relative to another.
+const RELATIVE_XRANDR_ARGS: &[&str] =
+    &["--left-of", "--right-of", "--above", "--below", "--same-as"];
+
+/// Absolute positions for the outputs of `profile` assigned in `assignment`, keyed by profile
+/// output name. Empty unless at least one of the outputs uses `placement`. Profiles without
+/// placements are only checked for overlaps and gaps when every lit output has a `pos` and a
+/// known size, and none is positioned by `xrandr_args`.
+pub(crate) fn positions(
+    profile: &Profile,
+    assignment: &BTreeMap<String, &Output>,
+) -> Result<BTreeMap<String, (i32, i32)>> {
+    let relative = profile.outputs.values().any(|o| o.placement.is_some());
+
+    let mut slots = Vec::new();
+    for (name, detected) in assignment {
+        let output = &profile.outputs[name];
+        if output.settings.off {
+            continue;
+        }
+        // an output placed against a missing optional output takes that output's place
+        let (mut pos, mut placement) = (output.settings.pos, output.placement.as_ref());
+        for _ in 0..profile.outputs.len() {
+            let target = match placement.map(|p| p.relation.target()) {
+                Some(target) if !assignment.contains_key(target) => target,
+                _ => break,
+            };
+            match profile.outputs.get(target) {
+                Some(missing) if missing.optional => {
+                    pos = missing.settings.pos;
+                    placement = missing.placement.as_ref();
+                }
+                _ => break,
+            }
+        }
+        let size = footprint(&output.settings, detected);
+        if !relative {
+            let positioned_by_args = output
+                .xrandr_args
+                .iter()
+                .flatten()
+                .any(|(arg, _)| RELATIVE_XRANDR_ARGS.contains(&arg.as_str()));
+            if pos.is_none() || size.is_none() || positioned_by_args {
+                return Ok(BTreeMap::new());
+            }
+        }
+        let size = size.ok_or_else(|| Error::UnknownOutputSize(name.clone()))?;
+        slots.push(Slot {
+            name,
+            size,
+            pos: pos.map(|p| (p.x, p.y)),
+            placement,
+        });
+    }
+
+    let positions = solve(&slots)?;
+    Ok(if relative { positions } else { BTreeMap::new() })
+}
+
+/// The area (x, y, width, height) each lit output of `profile` covers once applied, given the
+/// `positions` worked out for its relatively placed outputs. Outputs whose size is unknown are
+/// left out.
+pub(crate) fn areas(
+    profile: &Profile,
+    assignment: &BTreeMap<String, &Output>,
+    positions: &BTreeMap<String, (i32, i32)>,
+) -> BTreeMap<String, (i32, i32, i32, i32)> {
+    let mut areas = BTreeMap::new();
+    for (name, detected) in assignment {
+        let settings = &profile.outputs[name].settings;
+        if settings.off {
+            continue;
+        }
+        let (x, y) = positions
+            .get(name)
+            .copied()
+            .or_else(|| settings.pos.or(detected.settings.pos).map(|p| (p.x, p.y)))
+            .unwrap_or((0, 0));
+        if let Some((w, h)) = footprint(settings, detected) {
+            areas.insert(name.clone(), (x, y, w, h));
+        }
+    }
+    areas
+}
+
+/// Resolve relative placements to absolute positions, shifting the result so that nothing lies
+/// left of or above the origin, and check that the outputs neither overlap (except for mirrored
+/// outputs sharing the exact same area) nor leave gaps that split the screen.
+pub(crate) fn solve(slots: &[Slot]) -> Result<BTreeMap<String, (i32, i32)>> {
+    let mut placed: BTreeMap<&str, Rect> = BTreeMap::new();
+    for slot in slots.iter().filter(|s| s.placement.is_none()) {
+        let (x, y) = slot.pos.unwrap_or((0, 0));
+        let (w, h) = slot.size;
+        placed.insert(slot.name, Rect { x, y, w, h });
+    }
+
+    while placed.len() < slots.len() {
+        let mut progress = false;
+        for slot in slots {
+            if placed.contains_key(slot.name) {
+                continue;
+            }
+            let placement = slot.placement.unwrap();
+            let target = match placed.get(placement.relation.target()) {
+                Some(target) => *target,
+                None => continue,
+            };
+            let (w, h) = slot.size;
+            let (x, y) = match placement.relation {
+                Relation::RightOf(_) => (target.x + target.w, placement.align.offset(target.y, target.h, h)),
+                Relation::LeftOf(_) => (target.x - w, placement.align.offset(target.y, target.h, h)),
+                Relation::Below(_) => (placement.align.offset(target.x, target.w, w), target.y + target.h),
+                Relation::Above(_) => (placement.align.offset(target.x, target.w, w), target.y - h),
+            };
+            placed.insert(slot.name, Rect { x, y, w, h });
+            progress = true;
+        }
+
+        if !progress {
+            let pending = slots
+                .iter()
+                .filter(|s| !placed.contains_key(s.name))
+                .collect::<Vec<&Slot>>();
+            for slot in &pending {
+                let target = slot.placement.unwrap().relation.target();
+                if !slots.iter().any(|s| s.name == target) {
+                    return Err(Error::PlacementUnknownOutput(slot.name.into(), target.into()));
+                }
+            }
+            let names = pending.iter().map(|s| s.name).collect::<Vec<&str>>();
+            return Err(Error::PlacementCycle(names.join(", ")));
+        }
+    }
+
+    let min_x = placed.values().map(|r| r.x).min().unwrap_or(0).min(0);
+    let min_y = placed.values().map(|r| r.y).min().unwrap_or(0).min(0);
+    for rect in placed.values_mut() {
+        rect.x -= min_x;
+        rect.y -= min_y;
+    }
+
+    let rects = placed.iter().collect::<Vec<(&&str, &Rect)>>();
+    for (i, (a, ra)) in rects.iter().enumerate() {
+        for (b, rb) in &rects[i + 1..] {
+            if ra.overlaps(rb) && !ra.same(rb) {
+                return Err(Error::OverlappingOutputs(a.to_string(), b.to_string()));
+            }
+        }
+    }
+
+    // every output must be reachable from the first through outputs that share an edge
+    let mut reached = vec![false; rects.len()];
+    let mut queue = vec![0];
+    while let Some(i) = queue.pop() {
+        if reached.get(i) != Some(&false) {
+            continue;
+        }
+        reached[i] = true;
+        queue.extend((0..rects.len()).filter(|j| !reached[*j] && rects[i].1.touches(rects[*j].1)));
+    }
+    if let Some(i) = reached.iter().position(|r| !r) {
+        return Err(Error::DisconnectedLayout(rects[i].0.to_string()));
+    }
+
+    Ok(placed
+        .into_iter()
+        .map(|(name, r)| (name.to_string(), (r.x, r.y)))
+        .collect())
+}
+
+/// The area an output will cover on the screen given its settings: the size of its mode (the
+/// requested one, else its current one, else its preferred one), rotated, then scaled or
+/// transformed.
+fn footprint(settings: &OutputSettings, detected: &Output) -> Option<(i32, i32)> {
+    let (w, h) = match settings.mode.as_ref().or(detected.settings.mode.as_ref()) {
+        Some(name) => detected
+            .modes
+            .iter()
+            .find(|m| m.name == *name)
+            .map(|m| (m.width, m.height))
+            .or_else(|| name.parse::<Size>().ok().map(|s| (s.width, s.height)))?,
+        None => detected.preferred_mode().map(|m| (m.width, m.height))?,
+    };
+    let (w, h) = match settings.rotate {
+        Some(rotate) if rotate.is_sideways() => (h, w),
+        _ => (w, h),
+    };
+
+    let (w, h) = if let Some(from) = settings.scale_from {
+        (from.width, from.height)
+    } else if let Some(transform) = &settings.transform {
+        transformed_size(transform.matrix(), w, h)
+    } else if let Some(scale) = settings.scale {
+        let (sx, sy) = scale.factors();
+        ((w as f64 * sx).round() as u32, (h as f64 * sy).round() as u32)
+    } else {
+        (w, h)
+    };
+    Some((w as i32, h as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(yaml: &str) -> Placement {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn slot<'a>(name: &'a str, size: (i32, i32), placement: Option<&'a Placement>) -> Slot<'a> {
+        Slot {
+            name,
+            size,
+            pos: None,
+            placement,
+        }
+    }
+
+    #[test]
+    fn solve_relative_placements() {
+        let right = placement("right-of: laptop\nalign: bottom\n");
+        let above = placement("above: desk\nalign: center\n");
+        let left = placement("left-of: laptop\n");
+        let slots = [
+            slot("laptop", (1920, 1200), None),
+            slot("desk", (2560, 1440), Some(&right)),
+            slot("tv", (1280, 720), Some(&above)),
+            slot("side", (1080, 1920), Some(&left)),
+        ];
+
+        let positions = solve(&slots).unwrap();
+        // everything shifts right by the width of "side" and down by as much as "tv" sticks out
+        // above "laptop"
+        assert_eq!(positions["side"], (0, 960));
+        assert_eq!(positions["laptop"], (1080, 960));
+        assert_eq!(positions["desk"], (1080 + 1920, 960 - 240));
+        assert_eq!(positions["tv"], (1080 + 1920 + 640, 0));
+    }
+
+    #[test]
+    fn place_against_missing_optional_output() {
+        let profile: Profile = serde_yaml::from_str(
+            r#"
+outputs:
+  laptop:
+    edid: aa
+  tablet:
+    edid: bb
+    optional: true
+    placement:
+      below: laptop
+  desk:
+    edid: cc
+    placement:
+      right-of: tablet
+"#,
+        )
+        .unwrap();
+        let detected = |width, height| Output {
+            modes: vec![crate::OutputMode {
+                name: format!("{}x{}", width, height),
+                width,
+                height,
+                refresh: 60000,
+                preferred: true,
+            }],
+            ..Default::default()
+        };
+        let (laptop, desk) = (detected(1920, 1200), detected(2560, 1440));
+        let assignment = BTreeMap::from([("laptop".to_string(), &laptop), ("desk".to_string(), &desk)]);
+
+        // "desk" takes the place of "tablet", below "laptop"
+        let positions = positions(&profile, &assignment).unwrap();
+        assert_eq!(positions["laptop"], (0, 0));
+        assert_eq!(positions["desk"], (0, 1200));
+    }
+
+    #[test]
+    fn check_absolute_positions() {
+        let detected = Output {
+            modes: vec![crate::OutputMode {
+                name: "1920x1080".into(),
+                width: 1920,
+                height: 1080,
+                refresh: 60000,
+                preferred: true,
+            }],
+            ..Default::default()
+        };
+        let assignment = BTreeMap::from([("a".to_string(), &detected), ("b".to_string(), &detected)]);
+        let with_b_at = |pos: &str| {
+            let yaml = format!(
+                "outputs:\n  a:\n    settings: {{pos: 0x0}}\n  b:\n    settings: {{pos: {}}}\n",
+                pos
+            );
+            positions(&serde_yaml::from_str(&yaml).unwrap(), &assignment)
+        };
+
+        assert_eq!(with_b_at("1920x0").unwrap(), BTreeMap::new());
+        assert!(matches!(with_b_at("960x0"), Err(Error::OverlappingOutputs(..))));
+        assert!(matches!(with_b_at("3840x0"), Err(Error::DisconnectedLayout(..))));
+    }
+
+    #[test]
+    fn reject_bad_placements() {
+        let a_of_b = placement("right-of: b\n");
+        let b_of_a = placement("right-of: a\n");
+        assert!(matches!(
+            solve(&[slot("a", (10, 10), Some(&a_of_b)), slot("b", (10, 10), Some(&b_of_a))]),
+            Err(Error::PlacementCycle(_))
+        ));
+
+        let of_missing = placement("below: missing\n");
+        assert!(matches!(
+            solve(&[slot("a", (10, 10), None), slot("b", (10, 10), Some(&of_missing))]),
+            Err(Error::PlacementUnknownOutput(slot, target)) if slot == "b" && target == "missing"
+        ));
+
+        let mut far = slot("far", (10, 10), None);
+        far.pos = Some((100, 0));
+        let beside = placement("right-of: a\n");
+        assert!(matches!(
+            solve(&[slot("a", (10, 10), None), slot("b", (10, 10), Some(&beside)), far]),
+            Err(Error::DisconnectedLayout(name)) if name == "far"
+        ));
+
+        let mut overlapping = slot("c", (10, 10), None);
+        overlapping.pos = Some((15, 5));
+        assert!(matches!(
+            solve(&[slot("a", (10, 10), None), slot("b", (10, 10), Some(&beside)), overlapping]),
+            Err(Error::OverlappingOutputs(a, b)) if a == "b" && b == "c"
+        ));
+
+        // mirrored outputs cover exactly the same area
+        assert!(solve(&[slot("a", (10, 10), None), slot("b", (10, 10), None)]).is_ok());
+    }
+
+    #[test]
+    fn footprint_accounts_for_rotation_and_scale() {
+        let detected = Output {
+            modes: vec![crate::OutputMode {
+                name: "2560x1440".into(),
+                width: 2560,
+                height: 1440,
+                refresh: 60000,
+                preferred: true,
+            }],
+            ..Default::default()
+        };
+        let settings = |yaml: &str| serde_yaml::from_str::<OutputSettings>(yaml).unwrap();
+
+        assert_eq!(footprint(&settings("{}"), &detected), Some((2560, 1440)));
+        assert_eq!(footprint(&settings("rotate: left"), &detected), Some((1440, 2560)));
+        assert_eq!(
+            footprint(&settings("{rotate: right, scale: 1.5}"), &detected),
+            Some((2160, 3840))
+        );
+        assert_eq!(
+            footprint(&settings("{mode: 1920x1080, scale_from: 3840x2160}"), &detected),
+            Some((3840, 2160))
+        );
+        assert_eq!(
+            footprint(&settings("transform: \"0.5,0,0,0,0.5,0,0,0,1\""), &detected),
+            Some((1280, 720))
+        );
+    }
+}
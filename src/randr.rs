@@ -0,0 +1,970 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::ptr::{self, NonNull};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use x11::{xlib, xrandr as xrr};
+use xrandr::{Crtc, Mode, Output as XOutput, XId};
+
+use crate::layout::{Layout, OutputLayout};
+use crate::output::{transformed_size, Scale, Transform};
+use crate::{Error, Result};
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// How long to wait for a burst of RandR notifications (eg, from docking) to finish before acting
+/// on it.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The `xrandr --output` arguments the native backend can apply. Layouts using any others need
+/// `--backend xrandr`.
+pub(crate) const NATIVE_ARGS: &[&str] = &[
+    "--off",
+    "--auto",
+    "--primary",
+    "--mode",
+    "--rate",
+    "--refresh",
+    "--pos",
+    "--scale-from",
+    "--scale",
+    "--transform",
+    "--rotate",
+    "--rotation",
+    "--reflect",
+];
+
+/// The first argument in `layout` that the native backend can't apply, if any.
+pub(crate) fn unsupported_argument(layout: &Layout) -> Option<(&str, &str)> {
+    layout.outputs.iter().find_map(|o| {
+        o.args
+            .iter()
+            .find(|arg| arg.starts_with("--") && !NATIVE_ARGS.contains(&arg.as_str()))
+            .map(|arg| (o.name.as_str(), arg.as_str()))
+    })
+}
+
+/// Settings requested for a single output, parsed from its `xrandr --output` arguments.
+#[derive(Debug, Default, PartialEq)]
+struct Target {
+    off: bool,
+    auto: bool,
+    primary: bool,
+    mode: Option<String>,
+    rate: Option<f64>,
+    pos: Option<(i32, i32)>,
+    rotation: Option<u16>,
+    reflection: Option<u16>,
+    scale_from: Option<(i32, i32)>,
+    scale: Option<Scale>,
+    transform: Option<Transform>,
+}
+
+impl TryFrom<&OutputLayout> for Target {
+    type Error = Error;
+
+    fn try_from(output: &OutputLayout) -> Result<Target> {
+        let mut target = Target::default();
+        let mut args = output.args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| Error::MissingArgumentValue(output.name.clone(), arg.clone()))
+            };
+            let invalid =
+                |v: &str| Error::InvalidArgumentValue(output.name.clone(), arg.clone(), v.into());
+            match arg.as_str() {
+                "--off" => target.off = true,
+                "--auto" => target.auto = true,
+                "--primary" => target.primary = true,
+                "--mode" => target.mode = Some(value()?.clone()),
+                "--rate" | "--refresh" => {
+                    let v = value()?;
+                    target.rate = Some(v.parse().map_err(|_| invalid(v))?);
+                }
+                "--pos" => {
+                    let v = value()?;
+                    target.pos = Some(parse_pair(v).ok_or_else(|| invalid(v))?);
+                }
+                "--scale-from" => {
+                    let v = value()?;
+                    target.scale_from = Some(
+                        parse_pair(v)
+                            .filter(|(w, h)| *w > 0 && *h > 0)
+                            .ok_or_else(|| invalid(v))?,
+                    );
+                }
+                "--scale" => {
+                    let v = value()?;
+                    target.scale = Some(v.parse().map_err(|_| invalid(v))?);
+                }
+                "--transform" => {
+                    let v = value()?;
+                    target.transform = Some(v.parse().map_err(|_| invalid(v))?);
+                }
+                "--rotate" | "--rotation" => {
+                    let v = value()?;
+                    target.rotation = Some(match v.as_str() {
+                        "normal" => xrr::RR_Rotate_0,
+                        "left" => xrr::RR_Rotate_90,
+                        "inverted" => xrr::RR_Rotate_180,
+                        "right" => xrr::RR_Rotate_270,
+                        _ => return Err(invalid(v)),
+                    } as u16);
+                }
+                "--reflect" => {
+                    let v = value()?;
+                    target.reflection = Some(match v.as_str() {
+                        "normal" => 0,
+                        "x" => xrr::RR_Reflect_X,
+                        "y" => xrr::RR_Reflect_Y,
+                        "xy" => xrr::RR_Reflect_X | xrr::RR_Reflect_Y,
+                        _ => return Err(invalid(v)),
+                    } as u16);
+                }
+                _ => return Err(Error::UnsupportedArgument(output.name.clone(), arg.clone())),
+            }
+        }
+        Ok(target)
+    }
+}
+
+fn parse_pair(s: &str) -> Option<(i32, i32)> {
+    let (x, y) = s.split_once('x')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+/// The state of a single CRTC, either as it is now or as it should be after applying a `Plan`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct CrtcConfig {
+    pub crtc: XId,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub mode: XId,
+    pub rotation: u16,
+    pub outputs: Vec<XId>,
+    /// Projective transform from screen to CRTC coordinates, as 16.16 fixed point. `None` is the
+    /// identity.
+    pub transform: Option<[[i32; 3]; 3]>,
+}
+
+impl CrtcConfig {
+    fn is_enabled(&self) -> bool {
+        self.mode != 0 && !self.outputs.is_empty()
+    }
+
+    fn disable(&mut self) {
+        self.x = 0;
+        self.y = 0;
+        self.width = 0;
+        self.height = 0;
+        self.mode = 0;
+        self.rotation = xrr::RR_Rotate_0 as u16;
+        self.outputs.clear();
+        self.transform = None;
+    }
+
+    fn fits(&self, (width, height): (i32, i32)) -> bool {
+        self.x + self.width as i32 <= width && self.y + self.height as i32 <= height
+    }
+}
+
+impl From<&Crtc> for CrtcConfig {
+    fn from(c: &Crtc) -> CrtcConfig {
+        CrtcConfig {
+            crtc: c.xid,
+            x: c.x,
+            y: c.y,
+            width: c.width,
+            height: c.height,
+            mode: c.mode,
+            rotation: c.rotation as u16,
+            outputs: c.outputs.clone(),
+            transform: None,
+        }
+    }
+}
+
+/// The CRTC changes needed to go from the current server state to a `Layout`.
+#[derive(Debug)]
+pub(crate) struct Plan {
+    current: Vec<CrtcConfig>,
+    target: Vec<CrtcConfig>,
+    size: Option<(i32, i32)>,
+    primary: Option<XId>,
+    labels: HashMap<XId, String>,
+}
+
+impl Plan {
+    /// Work out CRTC assignments, modes, and the screen size needed to realize `layout` given the
+    /// server's current `outputs`, `crtcs`, and `modes`.
+    pub(crate) fn new(
+        layout: &Layout,
+        outputs: &[XOutput],
+        crtcs: &[Crtc],
+        modes: &[Mode],
+    ) -> Result<Plan> {
+        let current = crtcs.iter().map(CrtcConfig::from).collect::<Vec<CrtcConfig>>();
+        let mut target = current
+            .iter()
+            .map(|c| (c.crtc, c.clone()))
+            .collect::<BTreeMap<XId, CrtcConfig>>();
+        let mut labels = HashMap::new();
+        let mut primary = None;
+
+        let mut requested = Vec::new();
+        for output_layout in &layout.outputs {
+            let t = Target::try_from(output_layout)?;
+            let output = outputs
+                .iter()
+                .find(|o| o.name == output_layout.name)
+                .ok_or_else(|| Error::UnknownOutput(output_layout.name.clone()))?;
+            requested.push((output, t));
+        }
+
+        // release the crtcs of outputs being turned off first, so that outputs listed before them
+        // can take those crtcs over
+        for (output, _) in requested.iter().filter(|(_, t)| t.off) {
+            if let Some(config) = output.crtc.and_then(|id| target.get_mut(&id)) {
+                config.outputs.retain(|o| *o != output.xid);
+                if config.outputs.is_empty() {
+                    config.disable();
+                }
+            }
+        }
+
+        for (output, t) in requested.iter().filter(|(_, t)| !t.off) {
+            let crtc_id = match output.crtc {
+                Some(id) => id,
+                None => output
+                    .crtcs
+                    .iter()
+                    .copied()
+                    .find(|id| target.get(id).is_some_and(|c| c.outputs.is_empty()))
+                    .ok_or_else(|| Error::NoCrtcAvailable(output.name.clone()))?,
+            };
+            let config = target
+                .get_mut(&crtc_id)
+                .ok_or_else(|| Error::NoCrtcAvailable(output.name.clone()))?;
+
+            let mode = select_mode(output, t, config, modes)?;
+            let rotation = t.rotation.unwrap_or(if config.is_enabled() {
+                config.rotation & 0x0f
+            } else {
+                xrr::RR_Rotate_0 as u16
+            });
+            let reflection = t.reflection.unwrap_or(if config.is_enabled() {
+                config.rotation & 0x30
+            } else {
+                0
+            });
+            let (x, y) = t.pos.unwrap_or(if config.is_enabled() {
+                (config.x, config.y)
+            } else {
+                (0, 0)
+            });
+            let (width, height) = if rotation & (xrr::RR_Rotate_90 | xrr::RR_Rotate_270) as u16 != 0 {
+                (mode.height, mode.width)
+            } else {
+                (mode.width, mode.height)
+            };
+
+            // scaling stretches the area the crtc covers on the screen to the requested size
+            let matrix = if let Some((w, h)) = t.scale_from {
+                Some([
+                    [w as f64 / width as f64, 0.0, 0.0],
+                    [0.0, h as f64 / height as f64, 0.0],
+                    [0.0, 0.0, 1.0],
+                ])
+            } else if let Some(transform) = &t.transform {
+                Some(transform.matrix())
+            } else {
+                t.scale.map(|scale| {
+                    let (sx, sy) = scale.factors();
+                    [[sx, 0.0, 0.0], [0.0, sy, 0.0], [0.0, 0.0, 1.0]]
+                })
+            };
+            let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+            let ((width, height), transform) = match matrix.filter(|m| *m != identity) {
+                Some(m) => {
+                    let fixed = |v: f64| (v * 65536.0).round() as i32;
+                    (transformed_size(m, width, height), Some(m.map(|row| row.map(fixed))))
+                }
+                None => ((width, height), None),
+            };
+
+            config.x = x;
+            config.y = y;
+            config.width = width;
+            config.height = height;
+            config.transform = transform;
+            config.mode = mode.xid;
+            config.rotation = rotation | reflection;
+            if !config.outputs.contains(&output.xid) {
+                config.outputs.push(output.xid);
+            }
+            labels.insert(crtc_id, output.name.clone());
+
+            if t.primary {
+                primary = Some(output.xid);
+            }
+        }
+
+        let enabled = target.values().filter(|c| c.is_enabled());
+        let size = enabled.fold(None, |size: Option<(i32, i32)>, c| {
+            let (w, h) = size.unwrap_or((0, 0));
+            Some((
+                w.max(c.x + c.width as i32),
+                h.max(c.y + c.height as i32),
+            ))
+        });
+
+        Ok(Plan {
+            current,
+            target: target.into_values().collect(),
+            size,
+            primary,
+            labels,
+        })
+    }
+
+    fn label(&self, crtc: XId) -> String {
+        self.labels
+            .get(&crtc)
+            .cloned()
+            .unwrap_or_else(|| format!("crtc {}", crtc))
+    }
+}
+
+/// Pick the mode `output` should use: the named mode (closest to the requested rate, if any), the
+/// current mode when nothing else was asked for, or the preferred mode.
+fn select_mode<'a>(
+    output: &XOutput,
+    t: &Target,
+    current: &CrtcConfig,
+    modes: &'a [Mode],
+) -> Result<&'a Mode> {
+    let supported = output
+        .modes
+        .iter()
+        .filter_map(|id| modes.iter().find(|m| m.xid == *id))
+        .collect::<Vec<&Mode>>();
+
+    let base = match &t.mode {
+        Some(name) => supported
+            .iter()
+            .copied()
+            .find(|m| m.name == *name || format!("{}x{}", m.width, m.height) == *name)
+            .ok_or_else(|| Error::NoSuchMode(output.name.clone(), name.clone()))?,
+        None if !t.auto && current.is_enabled() && current.outputs.contains(&output.xid) => {
+            modes
+                .iter()
+                .find(|m| m.xid == current.mode)
+                .ok_or_else(|| Error::NoSuchMode(output.name.clone(), current.mode.to_string()))?
+        }
+        None => output
+            .preferred_modes
+            .iter()
+            .chain(output.modes.iter())
+            .find_map(|id| supported.iter().copied().find(|m| m.xid == *id))
+            .ok_or_else(|| Error::NoSuchMode(output.name.clone(), "preferred".into()))?,
+    };
+
+    let rate = match t.rate {
+        Some(rate) => rate,
+        None => return Ok(base),
+    };
+    Ok(supported
+        .into_iter()
+        .filter(|m| m.width == base.width && m.height == base.height && m.name == base.name)
+        .min_by(|a, b| (a.rate - rate).abs().total_cmp(&(b.rate - rate).abs()))
+        .unwrap_or(base))
+}
+
+/// Wait for a burst of notifications to end: sleep for `quiet` and `drain` whatever arrived,
+/// until a sleep passes without any.
+fn settle(quiet: Duration, mut drain: impl FnMut() -> usize) {
+    loop {
+        thread::sleep(quiet);
+        if drain() == 0 {
+            return;
+        }
+    }
+}
+
+/// First X protocol error reported while an `ErrorTrap` was installed.
+static X_ERROR: Mutex<Option<(u8, u8, u8)>> = Mutex::new(None);
+
+unsafe extern "C" fn record_error(_: *mut xlib::Display, event: *mut xlib::XErrorEvent) -> c_int {
+    if let Ok(mut slot) = X_ERROR.lock() {
+        if slot.is_none() {
+            let e = &*event;
+            *slot = Some((e.request_code, e.minor_code, e.error_code));
+        }
+    }
+    0
+}
+
+/// Temporarily replaces Xlib's default error handler (which exits the process) so that failed
+/// requests can be reported as `Error::RandrRequestFailed`.
+struct ErrorTrap {
+    previous: Option<unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int>,
+}
+
+impl ErrorTrap {
+    fn install() -> ErrorTrap {
+        if let Ok(mut slot) = X_ERROR.lock() {
+            *slot = None;
+        }
+        ErrorTrap {
+            previous: unsafe { xlib::XSetErrorHandler(Some(record_error)) },
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        unsafe { xlib::XSetErrorHandler(self.previous) };
+        match X_ERROR.lock().ok().and_then(|mut slot| slot.take()) {
+            Some((request, minor, code)) => Err(Error::RandrRequestFailed(request, minor, code)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A connection to the X server used to issue RandR requests and receive RandR notifications.
+pub(crate) struct Display {
+    sys: NonNull<xlib::Display>,
+    event_base: Option<c_int>,
+}
+
+impl Display {
+    pub(crate) fn open() -> Result<Display> {
+        let sys = NonNull::new(unsafe { xlib::XOpenDisplay(ptr::null()) })
+            .ok_or(Error::CannotOpenDisplay)?;
+        Ok(Display {
+            sys,
+            event_base: None,
+        })
+    }
+
+    fn root(&self) -> xlib::Window {
+        unsafe { xlib::XDefaultRootWindow(self.sys.as_ptr()) }
+    }
+
+    /// The RandR `PATH` property of an output, eg "mst:63-1" for a monitor behind a DisplayPort
+    /// hub.
+    pub(crate) fn output_path(&mut self, output: XId) -> Option<String> {
+        self.output_string_property(output, c"PATH")
+    }
+
+    /// The value of a string-typed output property, which the xrandr crate doesn't decode.
+    fn output_string_property(&mut self, output: XId, name: &CStr) -> Option<String> {
+        let dpy = self.sys.as_ptr();
+        let atom = unsafe { xlib::XInternAtom(dpy, name.as_ptr(), xlib::True) };
+        if atom == 0 {
+            return None;
+        }
+
+        let (mut actual_type, mut format, mut items, mut bytes_after) = (0, 0, 0, 0);
+        let mut data = ptr::null_mut();
+        let status = unsafe {
+            xrr::XRRGetOutputProperty(
+                dpy,
+                output,
+                atom,
+                0,
+                256,
+                xlib::False,
+                xlib::False,
+                xlib::XA_STRING,
+                &mut actual_type,
+                &mut format,
+                &mut items,
+                &mut bytes_after,
+                &mut data,
+            )
+        };
+        if status != 0 || data.is_null() {
+            return None;
+        }
+        let value = (actual_type == xlib::XA_STRING && format == 8).then(|| {
+            let bytes = unsafe { std::slice::from_raw_parts(data, items as usize) };
+            String::from_utf8_lossy(bytes).trim_end_matches('\0').to_string()
+        });
+        unsafe { xlib::XFree(data.cast()) };
+        value.filter(|v| !v.is_empty())
+    }
+
+    /// The reflection flags of `crtc` and its transform as 16.16 fixed point, `None` for the
+    /// identity; the xrandr crate reports neither.
+    pub(crate) fn crtc_reflection_and_transform(
+        &mut self,
+        crtc: XId,
+    ) -> Result<(u16, Option<[[i32; 3]; 3]>)> {
+        let dpy = self.sys.as_ptr();
+        let res = NonNull::new(unsafe { xrr::XRRGetScreenResourcesCurrent(dpy, self.root()) })
+            .ok_or(Error::XrandrError(xrandr::XrandrError::GetResources))?;
+        let info = unsafe { xrr::XRRGetCrtcInfo(dpy, res.as_ptr(), crtc) };
+        unsafe { xrr::XRRFreeScreenResources(res.as_ptr()) };
+        let info =
+            NonNull::new(info).ok_or(Error::XrandrError(xrandr::XrandrError::GetCrtc(crtc)))?;
+        let reflection =
+            unsafe { info.as_ref().rotation } & (xrr::RR_Reflect_X | xrr::RR_Reflect_Y) as u16;
+        unsafe { xrr::XRRFreeCrtcInfo(info.as_ptr()) };
+
+        let mut attributes = ptr::null_mut();
+        let status = unsafe { xrr::XRRGetCrtcTransform(dpy, crtc, &mut attributes) };
+        if status == 0 || attributes.is_null() {
+            return Ok((reflection, None));
+        }
+        let matrix = unsafe { (*attributes).currentTransform.matrix };
+        unsafe { xlib::XFree(attributes.cast()) };
+        let unit = 1 << 16;
+        let identity = [[unit, 0, 0], [0, unit, 0], [0, 0, unit]];
+        Ok((reflection, (matrix != identity).then_some(matrix)))
+    }
+
+    /// Block until the screen configuration or the set of connected outputs changes, then wait for
+    /// related notifications to settle.
+    pub(crate) fn wait_for_change(&mut self) -> Result<()> {
+        let dpy = self.sys.as_ptr();
+        let event_base = self.select_change_events()?;
+
+        let mut event = xlib::XEvent { type_: 0 };
+        loop {
+            unsafe { xlib::XNextEvent(dpy, &mut event) };
+            let kind = event.get_type() - event_base;
+            if kind == xrr::RRScreenChangeNotify {
+                unsafe { xrr::XRRUpdateConfiguration(&mut event) };
+                break;
+            } else if kind == xrr::RRNotify {
+                break;
+            }
+        }
+
+        self.discard_changes();
+        Ok(())
+    }
+
+    /// Drop any pending RandR notifications, eg those caused by applying a layout ourselves.
+    pub(crate) fn discard_changes(&mut self) {
+        let dpy = self.sys.as_ptr();
+        if self.event_base.is_none() {
+            return;
+        }
+
+        let mut event = xlib::XEvent { type_: 0 };
+        settle(SETTLE_TIME, || {
+            let mut drained = 0;
+            while unsafe { xlib::XPending(dpy) } > 0 {
+                unsafe {
+                    xlib::XNextEvent(dpy, &mut event);
+                    xrr::XRRUpdateConfiguration(&mut event);
+                }
+                drained += 1;
+            }
+            drained
+        });
+    }
+
+    /// Ask for notifications of output and screen changes. Changes made from here on are reported
+    /// by `wait_for_change`, even those that happen before it is called.
+    pub(crate) fn select_change_events(&mut self) -> Result<c_int> {
+        if let Some(base) = self.event_base {
+            return Ok(base);
+        }
+        let dpy = self.sys.as_ptr();
+        let (mut event_base, mut error_base) = (0, 0);
+        if unsafe { xrr::XRRQueryExtension(dpy, &mut event_base, &mut error_base) } == 0 {
+            return Err(Error::RandrUnavailable);
+        }
+
+        unsafe {
+            xrr::XRRSelectInput(
+                dpy,
+                self.root(),
+                xrr::RRScreenChangeNotifyMask | xrr::RROutputChangeNotifyMask,
+            );
+            xlib::XFlush(dpy);
+        }
+        self.event_base = Some(event_base);
+        Ok(event_base)
+    }
+
+    /// Apply `plan` with the server grabbed so that clients never observe a half-applied layout.
+    pub(crate) fn apply(&mut self, plan: &Plan) -> Result<()> {
+        let dpy = self.sys.as_ptr();
+        let res = NonNull::new(unsafe { xrr::XRRGetScreenResourcesCurrent(dpy, self.root()) })
+            .ok_or(Error::XrandrError(xrandr::XrandrError::GetResources))?;
+
+        let trap = ErrorTrap::install();
+        unsafe { xlib::XGrabServer(dpy) };
+        let result = self.apply_grabbed(plan, res.as_ptr());
+        unsafe {
+            xlib::XUngrabServer(dpy);
+            xlib::XSync(dpy, xlib::False);
+            xrr::XRRFreeScreenResources(res.as_ptr());
+        }
+        let trapped = trap.finish();
+
+        result.and(trapped)
+    }
+
+    fn apply_grabbed(&mut self, plan: &Plan, res: *mut xrr::XRRScreenResources) -> Result<()> {
+        let dpy = self.sys.as_ptr();
+        let screen = unsafe { xlib::XDefaultScreen(dpy) };
+        let current_size = unsafe {
+            (
+                xlib::XDisplayWidth(dpy, screen),
+                xlib::XDisplayHeight(dpy, screen),
+            )
+        };
+        let size = plan.size.unwrap_or(current_size);
+
+        // crtcs that are going away, or whose current geometry will not fit on the resized screen,
+        // must be disabled before the screen size changes.
+        let mut disabled = Vec::new();
+        for current in plan.current.iter().filter(|c| c.is_enabled()) {
+            let target = plan.target.iter().find(|t| t.crtc == current.crtc);
+            let keep = match target {
+                Some(t) => t.is_enabled() && (t == current || current.fits(size)),
+                None => true,
+            };
+            if !keep {
+                let mut off = current.clone();
+                off.disable();
+                self.set_crtc_config(plan, res, &mut off)?;
+                disabled.push(current.crtc);
+            }
+        }
+
+        if size != current_size {
+            self.set_screen_size(size, current_size)?;
+        }
+
+        for target in plan.target.iter().filter(|t| t.is_enabled()) {
+            let current = plan.current.iter().find(|c| c.crtc == target.crtc);
+            if current == Some(target) && !disabled.contains(&target.crtc) {
+                continue;
+            }
+            self.set_crtc_config(plan, res, &mut target.clone())?;
+        }
+
+        if let Some(output) = plan.primary {
+            unsafe { xrr::XRRSetOutputPrimary(dpy, self.root(), output) };
+        }
+
+        Ok(())
+    }
+
+    fn set_crtc_config(
+        &mut self,
+        plan: &Plan,
+        res: *mut xrr::XRRScreenResources,
+        config: &mut CrtcConfig,
+    ) -> Result<()> {
+        if config.is_enabled() {
+            self.set_crtc_transform(config);
+        }
+
+        let outputs = if config.outputs.is_empty() {
+            ptr::null_mut()
+        } else {
+            config.outputs.as_mut_ptr()
+        };
+        let status = unsafe {
+            xrr::XRRSetCrtcConfig(
+                self.sys.as_ptr(),
+                res,
+                config.crtc,
+                xlib::CurrentTime,
+                config.x,
+                config.y,
+                config.mode,
+                config.rotation,
+                outputs,
+                config.outputs.len() as c_int,
+            )
+        };
+        if status != xrr::RRSetConfigSuccess {
+            return Err(Error::CrtcConfigFailed(plan.label(config.crtc), status));
+        }
+        Ok(())
+    }
+
+    /// Set the crtc's transform ahead of its mode so that the new geometry takes the scaling into
+    /// account; xrandr does the same.
+    fn set_crtc_transform(&mut self, config: &CrtcConfig) {
+        let unit = 1 << 16;
+        let (matrix, filter) = match config.transform {
+            Some(matrix) => (matrix, c"bilinear"),
+            None => ([[unit, 0, 0], [0, unit, 0], [0, 0, unit]], c"nearest"),
+        };
+        let mut transform = x11::xrender::XTransform { matrix };
+        unsafe {
+            xrr::XRRSetCrtcTransform(
+                self.sys.as_ptr(),
+                config.crtc,
+                &mut transform,
+                filter.as_ptr(),
+                ptr::null_mut(),
+                0,
+            )
+        };
+    }
+
+    fn set_screen_size(&mut self, (width, height): (i32, i32), current: (i32, i32)) -> Result<()> {
+        let dpy = self.sys.as_ptr();
+        let (mut min_w, mut min_h, mut max_w, mut max_h) = (0, 0, 0, 0);
+        unsafe {
+            xrr::XRRGetScreenSizeRange(dpy, self.root(), &mut min_w, &mut min_h, &mut max_w, &mut max_h)
+        };
+        if width > max_w || height > max_h {
+            return Err(Error::ScreenSizeTooLarge(width, height, max_w, max_h));
+        }
+        let (width, height) = (width.max(min_w), height.max(min_h));
+
+        // keep the current DPI when working out the physical size of the resized screen
+        let screen = unsafe { xlib::XDefaultScreen(dpy) };
+        let height_mm = unsafe { xlib::XDisplayHeightMM(dpy, screen) };
+        let dpi = if height_mm > 0 {
+            MM_PER_INCH * current.1 as f64 / height_mm as f64
+        } else {
+            96.0
+        };
+        let to_mm = |px: i32| (MM_PER_INCH * px as f64 / dpi).round() as c_int;
+
+        unsafe {
+            xrr::XRRSetScreenSize(dpy, self.root(), width, height, to_mm(width), to_mm(height))
+        };
+        Ok(())
+    }
+}
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        unsafe { xlib::XCloseDisplay(self.sys.as_ptr()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use indexmap::IndexMap;
+    use xrandr::Rotation;
+
+    fn mode(xid: XId, width: u32, height: u32, rate: f64) -> Mode {
+        Mode {
+            xid,
+            width,
+            height,
+            dot_clock: 0,
+            hsync_tart: 0,
+            hsync_end: 0,
+            htotal: 0,
+            hskew: 0,
+            vsync_start: 0,
+            vsync_end: 0,
+            vtotal: 0,
+            name: format!("{}x{}", width, height),
+            flags: 0,
+            rate,
+        }
+    }
+
+    fn output(xid: XId, name: &str, crtc: Option<XId>, modes: Vec<XId>) -> XOutput {
+        XOutput {
+            xid,
+            properties: IndexMap::new(),
+            timestamp: 0,
+            is_primary: false,
+            crtc,
+            name: name.into(),
+            mm_width: 0,
+            mm_height: 0,
+            connected: true,
+            subpixel_order: 0,
+            crtcs: vec![10, 11],
+            clones: Vec::new(),
+            preferred_modes: modes[..1].to_vec(),
+            current_mode: None,
+            modes,
+        }
+    }
+
+    fn crtc(xid: XId, mode: XId, width: u32, height: u32, outputs: Vec<XId>) -> Crtc {
+        Crtc {
+            xid,
+            timestamp: 0,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            mode,
+            rotation: Rotation::Normal,
+            outputs,
+            rotations: 0,
+            possible: vec![1, 2],
+        }
+    }
+
+    #[test]
+    fn plan_enables_output_on_free_crtc_and_grows_screen() {
+        let modes = vec![
+            mode(100, 1920, 1080, 60.0),
+            mode(101, 2560, 1440, 59.95),
+            mode(102, 2560, 1440, 143.9),
+        ];
+        let outputs = vec![
+            output(1, "eDP-1", Some(10), vec![100]),
+            output(2, "DP-1", None, vec![101, 102]),
+        ];
+        let crtcs = vec![crtc(10, 100, 1920, 1080, vec![1]), crtc(11, 0, 0, 0, vec![])];
+
+        let mut layout = Layout::default();
+        layout.set("eDP-1", vec!["--primary".into()]);
+        layout.set(
+            "DP-1",
+            ["--mode", "2560x1440", "--rate", "144", "--pos", "1920x0", "--rotate", "left"]
+                .map(String::from)
+                .to_vec(),
+        );
+
+        let plan = Plan::new(&layout, &outputs, &crtcs, &modes).unwrap();
+        assert_eq!(plan.size, Some((1920 + 1440, 2560)));
+        assert_eq!(plan.primary, Some(1));
+        assert_eq!(plan.target[0], CrtcConfig::from(&crtcs[0]));
+        assert_eq!(
+            plan.target[1],
+            CrtcConfig {
+                crtc: 11,
+                x: 1920,
+                y: 0,
+                width: 1440,
+                height: 2560,
+                mode: 102,
+                rotation: xrr::RR_Rotate_90 as u16,
+                outputs: vec![2],
+                transform: None,
+            }
+        );
+    }
+
+    #[test]
+    fn plan_reuses_crtc_of_output_turned_off_later() {
+        let modes = vec![mode(100, 2560, 1440, 60.0)];
+        let mut outputs = vec![
+            output(1, "DP-1", Some(10), vec![100]),
+            output(2, "DP-3", None, vec![100]),
+        ];
+        // the only crtc DP-3 can use is the one driving DP-1
+        outputs[1].crtcs = vec![10];
+        let crtcs = vec![crtc(10, 100, 2560, 1440, vec![1]), crtc(11, 0, 0, 0, vec![])];
+
+        let mut layout = Layout::default();
+        layout.set("DP-3", ["--mode", "2560x1440", "--pos", "0x0"].map(String::from).to_vec());
+        layout.off("DP-1");
+
+        let plan = Plan::new(&layout, &outputs, &crtcs, &modes).unwrap();
+        assert_eq!(plan.target[0].outputs, vec![2]);
+        assert_eq!(plan.target[0].mode, 100);
+        assert!(!plan.target[1].is_enabled());
+    }
+
+    #[test]
+    fn plan_scales_output_from_larger_resolution() {
+        let modes = vec![mode(100, 2560, 1440, 60.0), mode(101, 1920, 1080, 60.0)];
+        let outputs = vec![
+            output(1, "eDP-1", Some(10), vec![100]),
+            output(2, "HDMI-1", None, vec![101]),
+        ];
+        let crtcs = vec![crtc(10, 100, 2560, 1440, vec![1]), crtc(11, 0, 0, 0, vec![])];
+
+        let mut layout = Layout::default();
+        layout.set(
+            "HDMI-1",
+            ["--mode", "1920x1080", "--pos", "0x0", "--scale-from", "2560x1440"]
+                .map(String::from)
+                .to_vec(),
+        );
+
+        let plan = Plan::new(&layout, &outputs, &crtcs, &modes).unwrap();
+        assert_eq!(plan.size, Some((2560, 1440)));
+        let scaled = &plan.target[1];
+        assert_eq!((scaled.width, scaled.height, scaled.mode), (2560, 1440, 101));
+        assert_eq!(
+            scaled.transform,
+            Some([[87381, 0, 0], [0, 87381, 0], [0, 0, 65536]])
+        );
+
+        let mut layout = Layout::default();
+        layout.set("HDMI-1", ["--scale", "2x1.5"].map(String::from).to_vec());
+        let plan = Plan::new(&layout, &outputs, &crtcs, &modes).unwrap();
+        assert_eq!((plan.target[1].width, plan.target[1].height), (3840, 1620));
+        assert_eq!(
+            plan.target[1].transform,
+            Some([[131072, 0, 0], [0, 98304, 0], [0, 0, 65536]])
+        );
+
+        let mut layout = Layout::default();
+        layout.set("HDMI-1", vec!["--scale-from".into(), "2560".into()]);
+        assert!(matches!(
+            Plan::new(&layout, &outputs, &crtcs, &modes),
+            Err(Error::InvalidArgumentValue(..))
+        ));
+    }
+
+    #[test]
+    fn plan_rejects_unknown_mode_and_argument() {
+        let modes = vec![mode(100, 1920, 1080, 60.0)];
+        let outputs = vec![output(1, "eDP-1", Some(10), vec![100])];
+        let crtcs = vec![crtc(10, 100, 1920, 1080, vec![1])];
+
+        let mut layout = Layout::default();
+        layout.set("eDP-1", vec!["--mode".into(), "800x600".into()]);
+        assert!(matches!(
+            Plan::new(&layout, &outputs, &crtcs, &modes),
+            Err(Error::NoSuchMode(..))
+        ));
+
+        let mut layout = Layout::default();
+        layout.set("eDP-1", vec!["--rotat".into(), "left".into()]);
+        assert!(matches!(
+            Plan::new(&layout, &outputs, &crtcs, &modes),
+            Err(Error::UnsupportedArgument(..))
+        ));
+    }
+
+    #[test]
+    fn find_arguments_needing_xrandr() {
+        let mut layout = Layout::default();
+        layout.set("eDP-1", ["--mode", "1920x1080", "--pos", "0x0"].map(String::from).to_vec());
+        assert_eq!(unsupported_argument(&layout), None);
+
+        layout.set("HDMI-1", ["--auto", "--right-of", "eDP-1"].map(String::from).to_vec());
+        assert_eq!(unsupported_argument(&layout), Some(("HDMI-1", "--right-of")));
+    }
+
+    #[test]
+    fn settle_waits_out_a_burst() {
+        let mut bursts = vec![3, 1, 0, 5].into_iter();
+        let mut drains = 0;
+        settle(Duration::from_millis(1), || {
+            drains += 1;
+            bursts.next().unwrap()
+        });
+        assert_eq!(drains, 3);
+
+        let mut drains = 0;
+        settle(Duration::from_millis(1), || {
+            drains += 1;
+            0
+        });
+        assert_eq!(drains, 1);
+    }
+}
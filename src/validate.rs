@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Settings;
+use crate::randr::NATIVE_ARGS;
+use crate::{Backend, Error, Monitor, Output, Profile, Result};
+
+/// Arguments that `xrandr --output` accepts but that have no typed setting, and so may be passed
+/// through `xrandr_args`.
+const XRANDR_OUTPUT_ARGS: &[&str] = &[
+    "--preferred",
+    "--left-of",
+    "--right-of",
+    "--above",
+    "--below",
+    "--same-as",
+    "--set",
+    "--filter",
+    "--crtc",
+    "--panning",
+    "--gamma",
+    "--brightness",
+];
+
+/// A problem found in a configuration file.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Issue {
+    pub file: PathBuf,
+    /// Line and column, when known.
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, column)) = self.location {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Issue {
+    fn new(file: &Path, message: String) -> Issue {
+        Issue {
+            file: file.into(),
+            location: None,
+            message,
+        }
+    }
+
+    fn from_error(file: &Path, e: Error) -> Issue {
+        match e {
+            Error::SerdeYamlError(e) => {
+                let message = e.to_string();
+                // the location is reported separately
+                let message = match message.rfind(" at line ") {
+                    Some(i) => message[..i].to_string(),
+                    None => message,
+                };
+                Issue {
+                    file: file.into(),
+                    location: e.location().map(|l| (l.line(), l.column())),
+                    message,
+                }
+            }
+            e => Issue::new(file, e.to_string()),
+        }
+    }
+}
+
+/// Check the configuration in `dir` (`config.yaml`, `monitors/`, and `profiles/`) without
+/// connecting to the X server, returning every problem found. Arguments are checked against what
+/// `backend` can apply.
+pub fn validate(dir: &Path, backend: Backend) -> Result<Vec<Issue>> {
+    let mut issues = Vec::new();
+
+    let settings = dir.join("config.yaml");
+    if let Err(e) = Settings::load(&settings) {
+        issues.push(Issue::from_error(&settings, e));
+    }
+
+    let mut monitors = Vec::new();
+    for (path, result) in read_dir::<Monitor>(&dir.join("monitors"))? {
+        match result {
+            Ok(monitor) => monitors.push(monitor),
+            Err(e) => issues.push(Issue::from_error(&path, e)),
+        }
+    }
+
+    let mut loaded = Vec::new();
+    for (path, result) in read_dir::<Profile>(&dir.join("profiles"))? {
+        match result {
+            Ok(profile) => loaded.push((path, profile)),
+            Err(e) => issues.push(Issue::from_error(&path, e)),
+        }
+    }
+
+    let mut names: BTreeMap<&str, &Path> = BTreeMap::new();
+    for (path, profile) in &loaded {
+        if let Some(first) = names.insert(profile.name(), path) {
+            issues.push(Issue::new(
+                path,
+                format!("profile name '{}' is also used by {}", profile.name(), first.display()),
+            ));
+        }
+    }
+
+    let raw = loaded.iter().map(|(_, p)| p.clone()).collect::<Vec<Profile>>();
+    let mut monitor_sets: Vec<(Vec<String>, String)> = Vec::new();
+    for (path, profile) in &loaded {
+        let resolved = profile.inherit(&raw).and_then(|mut p| {
+            p.resolve_monitors(&monitors)?;
+            Ok(p)
+        });
+        let profile = match resolved {
+            Ok(p) => p,
+            Err(e) => {
+                issues.push(Issue::from_error(path, e));
+                continue;
+            }
+        };
+
+        for (name, output) in &profile.outputs {
+            // the mirror profile's output stands for every monitor
+            if profile.name() != "mirror" && !output.identifies_monitor() {
+                issues.push(Issue::new(
+                    path,
+                    format!("output '{}' has no edid, match, monitor, or connector", name),
+                ));
+            }
+            for arg in output.xrandr_args.iter().flatten().map(|(arg, _)| arg) {
+                if !XRANDR_OUTPUT_ARGS.contains(&arg.as_str()) {
+                    issues.push(Issue::new(
+                        path,
+                        format!("output '{}': unknown xrandr argument '{}'", name, arg),
+                    ));
+                } else if backend == Backend::Native && !NATIVE_ARGS.contains(&arg.as_str()) {
+                    issues.push(Issue::new(
+                        path,
+                        Error::UnsupportedArgument(name.clone(), arg.clone()).to_string(),
+                    ));
+                }
+            }
+        }
+
+        // identical monitors may appear more than once
+        let mut monitors = profile
+            .outputs
+            .values()
+            .filter(|o| o.identifies_monitor())
+            .map(identity)
+            .collect::<Vec<String>>();
+        monitors.sort();
+        if monitors.is_empty() {
+            continue;
+        }
+        match monitor_sets.iter().find(|(set, _)| *set == monitors) {
+            Some((_, other)) => issues.push(Issue::new(
+                path,
+                format!(
+                    "profile '{}' matches the same monitors as profile '{}'",
+                    profile.name(),
+                    other
+                ),
+            )),
+            None => monitor_sets.push((monitors, profile.name().into())),
+        }
+    }
+
+    Ok(issues)
+}
+
+/// What identifies the monitor for a profile output.
+fn identity(output: &Output) -> String {
+    let mut edids = output.edid.iter().flat_map(|e| e.iter()).cloned().collect::<Vec<String>>();
+    edids.sort();
+    format!(
+        "{:?} {:?} {:?} {:?} {}",
+        edids, output.matches, output.connector, output.index, output.optional
+    )
+}
+
+/// Load every entry of `dir`, in file name order, keeping failures.
+fn read_dir<T>(dir: &Path) -> Result<Vec<(PathBuf, Result<T>)>>
+where
+    T: TryFrom<fs::DirEntry, Error = Error>,
+{
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<fs::DirEntry>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.path(), T::try_from(entry)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TempDir;
+
+    #[test]
+    fn report_problems_in_profiles() {
+        let dir = TempDir::new("validate");
+        let profiles = dir.join("profiles");
+        fs::create_dir_all(&profiles).unwrap();
+        let write = |name: &str, contents: &str| fs::write(profiles.join(name), contents).unwrap();
+
+        write("desk.yaml", "outputs:\n  left:\n    edid: aa\n  right:\n    edid: bb\n");
+        write("desk-copy.yaml", "outputs:\n  l:\n    edid: aa\n  r:\n    edid: bb\n");
+        write("renamed.yaml", "profile_name: desk\noutputs:\n  main:\n    edid: cc\n");
+        write("typo.yaml", "outputs:\n  main:\n    edid: dd\n    settings:\n      rotat: left\n");
+        write(
+            "loose.yaml",
+            "outputs:\n  main:\n    settings:\n      mode: 1920x1080\n  tv:\n    edid: ee\n    xrandr_args:\n      --brightness: \"0.8\"\n      --frobnicate: \"1\"\n",
+        );
+        write("orphan.yaml", "extends: nowhere\noutputs: {}\n");
+        write("misspelled.yaml", "outputs:\n  main:\n    edid: ff\npriorty: 2\n");
+
+        let check = |backend: Backend| {
+            validate(&dir, backend)
+                .unwrap()
+                .into_iter()
+                .map(|i| {
+                    let file = i.file.file_name().unwrap().to_string_lossy().to_string();
+                    (file, i.location, i.message)
+                })
+                .collect::<Vec<_>>()
+        };
+        let issues = check(Backend::Xrandr);
+        let native = check(Backend::Native);
+
+        let find = |file: &str| {
+            issues
+                .iter()
+                .filter(|(f, _, _)| f == file)
+                .map(|(_, location, message)| (*location, message.as_str()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            find("desk.yaml"),
+            [(None, "profile 'desk' matches the same monitors as profile 'desk-copy'")]
+        );
+        assert!(find("desk-copy.yaml").is_empty());
+        let renamed = find("renamed.yaml");
+        assert_eq!(renamed.len(), 1);
+        assert!(renamed[0].1.starts_with("profile name 'desk' is also used by "));
+        assert!(renamed[0].1.ends_with("desk.yaml"));
+        let typo = find("typo.yaml");
+        assert_eq!(typo.len(), 1);
+        assert_eq!(typo[0].0, Some((5, 7)));
+        assert!(typo[0].1.starts_with("outputs.main.settings: unknown field `rotat`"), "{}", typo[0].1);
+        assert_eq!(
+            find("loose.yaml"),
+            [
+                (None, "output 'main' has no edid, match, monitor, or connector"),
+                (None, "output 'tv': unknown xrandr argument '--frobnicate'"),
+            ]
+        );
+        assert_eq!(native.len(), issues.len() + 1);
+        assert!(native.contains(&(
+            "loose.yaml".into(),
+            None,
+            "output 'tv': the native backend can't apply '--brightness'; use --backend xrandr"
+                .into()
+        )));
+        let misspelled = find("misspelled.yaml");
+        assert_eq!(misspelled.len(), 1);
+        assert_eq!(misspelled[0].0, Some((4, 1)));
+        assert!(misspelled[0].1.starts_with("unknown field `priorty`"), "{}", misspelled[0].1);
+        assert_eq!(
+            find("orphan.yaml"),
+            [(None, "profile 'orphan' extends unknown profile 'nowhere'")]
+        );
+    }
+}
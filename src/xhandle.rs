@@ -1,33 +1,178 @@
-use xrandr::XHandle;
+use x11::xrandr::{RR_Reflect_X, RR_Reflect_Y};
+use xrandr::{Output as XOutput, Rotation, ScreenResources, XHandle};
 
-use crate::{Output, Result};
+use crate::layout::Layout;
+use crate::randr::{Display, Plan};
+use crate::output::{Position, Rate, Reflect, Rotate, Transform};
+use crate::{Output, OutputMode, Result};
 
 #[cfg_attr(test, faux::create)]
-pub(crate) struct XHandleWrapper(XHandle);
-
-impl XHandleWrapper {
-    pub(crate) fn open() -> Result<XHandleWrapper> {
-        Ok(XHandleWrapper(XHandle::open()?))
-    }
+pub(crate) struct XHandleWrapper {
+    handle: XHandle,
+    // `XHandle` keeps its connection private, so RandR requests that the xrandr crate does not
+    // expose go through a second connection, opened only once one of them is needed.
+    display: Option<Display>,
 }
 
 #[cfg_attr(test, faux::methods)]
 impl XHandleWrapper {
+    pub fn open() -> Result<XHandleWrapper> {
+        Ok(XHandleWrapper {
+            handle: XHandle::open()?,
+            display: None,
+        })
+    }
+
     pub fn active_outputs(&mut self) -> Result<Vec<Output>> {
-        Ok(self
-            .0
+        let res = ScreenResources::new(&mut self.handle)?;
+        let xoutputs = self
+            .handle
             .monitors()?
+            .into_iter()
+            .flat_map(|m| m.outputs)
+            .collect::<Vec<XOutput>>();
+
+        xoutputs
             .iter()
-            .flat_map(|m| m.outputs.iter().map(|xoutput| xoutput.into()))
-            .collect::<Vec<Output>>())
+            .map(|xoutput| self.detected(&res, xoutput))
+            .collect()
     }
 
     pub fn inactive_outputs(&mut self) -> Result<Vec<Output>> {
-        Ok(self
-            .0
-            .all_outputs()?
+        let res = ScreenResources::new(&mut self.handle)?;
+        // outputs that were unplugged may still be driven by a crtc
+        res.outputs(&mut self.handle)?
             .iter()
-            .map(|o| o.into())
-            .collect::<Vec<Output>>())
+            .map(|xoutput| self.detected(&res, xoutput))
+            .collect()
+    }
+
+    fn detected(&mut self, res: &ScreenResources, xoutput: &XOutput) -> Result<Output> {
+        let mut output = with_crtc_state(&mut self.handle, res, xoutput)?;
+        // the xrandr crate lists PATH but can't decode strings
+        if xoutput.properties.contains_key("PATH") {
+            output.path = display(&mut self.display)?.output_path(xoutput.xid);
+        }
+        Ok(output)
+    }
+
+    /// Apply `layout` directly through RandR requests.
+    pub fn apply(&mut self, layout: &Layout) -> Result<()> {
+        let res = ScreenResources::new(&mut self.handle)?;
+        let outputs = res.outputs(&mut self.handle)?;
+        let crtcs = res.crtcs(&mut self.handle)?;
+        let plan = Plan::new(layout, &outputs, &crtcs, &res.modes())?;
+        log::debug!("{:?}", plan);
+        display(&mut self.display)?.apply(&plan)
+    }
+
+    /// The reflection and transform of the output called `name`, which detection doesn't read.
+    pub fn reflection_and_transform(
+        &mut self,
+        name: &str,
+    ) -> Result<(Option<Reflect>, Option<Transform>)> {
+        let res = ScreenResources::new(&mut self.handle)?;
+        let crtc = res
+            .outputs(&mut self.handle)?
+            .into_iter()
+            .find(|o| o.name == name)
+            .and_then(|o| o.crtc);
+        let crtc = match crtc {
+            Some(crtc) => crtc,
+            None => return Ok((None, None)),
+        };
+
+        let (reflection, matrix) = display(&mut self.display)?.crtc_reflection_and_transform(crtc)?;
+        let reflected = |flag: i32| reflection & flag as u16 != 0;
+        let reflect = match (reflected(RR_Reflect_X), reflected(RR_Reflect_Y)) {
+            (false, false) => None,
+            (true, false) => Some(Reflect::X),
+            (false, true) => Some(Reflect::Y),
+            (true, true) => Some(Reflect::Xy),
+        };
+        let transform = matrix
+            .map(|m| {
+                m.iter()
+                    .flatten()
+                    .map(|v| (*v as f64 / 65536.0).to_string())
+                    .collect::<Vec<String>>()
+                    .join(",")
+                    .parse::<Transform>()
+            })
+            .transpose()?;
+        Ok((reflect, transform))
+    }
+
+    /// Start listening for changes to the screen or its outputs, so that `wait_for_change` also
+    /// sees those that happen before it is called.
+    pub fn watch_changes(&mut self) -> Result<()> {
+        display(&mut self.display)?.select_change_events()?;
+        Ok(())
     }
+
+    /// Block until RandR reports a change to the screen or its outputs.
+    pub fn wait_for_change(&mut self) -> Result<()> {
+        display(&mut self.display)?.wait_for_change()
+    }
+
+    /// Discard pending change notifications, eg those caused by our own `apply`.
+    pub fn discard_changes(&mut self) {
+        if let Some(display) = &mut self.display {
+            display.discard_changes()
+        }
+    }
+}
+
+/// The second connection, opening it if this is its first use.
+fn display(display: &mut Option<Display>) -> Result<&mut Display> {
+    if display.is_none() {
+        *display = Some(Display::open()?);
+    }
+    Ok(display.as_mut().expect("display was just opened"))
+}
+
+/// Convert an xrandr output into an `Output` listing its supported modes.
+fn with_modes(res: &ScreenResources, xoutput: &XOutput) -> Output {
+    let mut output: Output = xoutput.into();
+    output.modes = xoutput
+        .modes
+        .iter()
+        .filter_map(|id| res.mode(*id).ok())
+        .map(|m| OutputMode {
+            name: m.name.clone(),
+            width: m.width,
+            height: m.height,
+            refresh: (m.rate * 1000.0).round() as u32,
+            preferred: xoutput.preferred_modes.contains(&m.xid),
+        })
+        .collect();
+    output
+}
+
+/// Convert an active xrandr output into an `Output` whose settings describe its current mode,
+/// rate, position, and rotation.
+fn with_crtc_state(handle: &mut XHandle, res: &ScreenResources, xoutput: &XOutput) -> Result<Output> {
+    let mut output = with_modes(res, xoutput);
+    let crtc_id = match xoutput.crtc {
+        Some(id) => id,
+        None => return Ok(output),
+    };
+
+    let crtc = res.crtc(handle, crtc_id)?;
+    let mode = res.mode(crtc.mode)?;
+
+    output.settings.mode = Some(mode.name.clone());
+    output.settings.rate = Rate::try_from(mode.rate).ok();
+    output.settings.pos = Some(Position {
+        x: crtc.x,
+        y: crtc.y,
+    });
+    output.settings.rotate = Some(match crtc.rotation {
+        Rotation::Normal => Rotate::Normal,
+        Rotation::Left => Rotate::Left,
+        Rotation::Inverted => Rotate::Inverted,
+        Rotation::Right => Rotate::Right,
+    });
+
+    Ok(output)
 }